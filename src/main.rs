@@ -1,16 +1,34 @@
-mod models;
-
-use std::{io, thread};
+use std::{env, fs, io, thread};
+use std::path::Path;
 use std::sync::mpsc;
-use std::time::Duration;
-use ratatui::prelude::Stylize;
-use ratatui::widgets::{Block, Borders, Gauge, Widget};
-use crate::models::tui_app::{Event, App, handle_input_events};
+use rust_project::models::config::{CONFIG_FILE, Config};
+use rust_project::models::tui_app::{Event, App, handle_input_events};
+use rust_project::models::wav_file::WavFile;
+
+const STDIN_OUTPUT_FILE: &str = "stdin.wav";
 
 fn main() -> io::Result<()> {
+    // Pipelines skip the TUI: cat file.wav | denoiser --stdin [--stdout] > denoised.wav
+    let to_stdout = env::args().any(|arg| arg == "--stdout");
+    if env::args().any(|arg| arg == "--stdin") {
+        return denoise_stdin(to_stdout);
+    }
+    if to_stdout {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--stdout requires --stdin"));
+    }
+
+    // denoiser --in-place file.wav overwrites the file, keeping the original as file.wav.bak
+    let args: Vec<String> = env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--in-place") {
+        let path = args.get(index + 1).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "--in-place requires a file path")
+        })?;
+        return denoise_in_place(path);
+    }
+
     // let file_path = "noise_example.wav";
     // let mut wav = WavFile::from_wav_file(file_path).unwrap();
-    // wav.denoise_data_fft(0.001).expect("Błont");
+    // wav.denoise_data_fft(0.001, None).expect("Błont");
     //
     // wav.save_to_file("new_file.wav");
 
@@ -38,5 +56,34 @@ fn main() -> io::Result<()> {
     app_result
   }
 
+fn denoise_in_place(path: &str) -> io::Result<()> {
+    // Same threshold as everywhere else, from the config file
+    let config = Config::load(Path::new(CONFIG_FILE))?;
+    WavFile::denoise_in_place(path, config.threshold).map_err(io::Error::other)?;
+    eprintln!("Denoised {}, the original was kept as {}.bak", path, path);
+    Ok(())
+}
+
+fn denoise_stdin(to_stdout: bool) -> io::Result<()> {
+    // Denoised with the configured threshold and saved into the output directory,
+    // or written to stdout for the next program in the pipeline
+    // Messages go to stderr, stdout carries nothing but the WAV file
+    let config = Config::load(Path::new(CONFIG_FILE))?;
+
+    let mut wav = WavFile::from_reader(io::stdin().lock()).map_err(io::Error::other)?;
+    wav.denoise_data_fft(config.threshold, None).map_err(io::Error::other)?;
+
+    if to_stdout {
+        return wav.write_to(io::stdout().lock()).map_err(io::Error::other);
+    }
 
+    fs::create_dir_all(&config.output_dir)?;
+    let save_path = config.output_dir.join(STDIN_OUTPUT_FILE);
+    let save_path = save_path
+        .to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid save path"))?;
+    wav.save_to_file(save_path).map_err(io::Error::other)?;
 
+    eprintln!("Denoised file saved to {}", save_path);
+    Ok(())
+}
@@ -1,7 +1,13 @@
 use std::fmt::Display;
 use crate::models::errors::WavError;
 
-#[derive(Debug, Clone)]
+// Range of the 24-bit samples, which are kept in i32
+const I24_MIN: i32 = -(1 << 23);
+const I24_MAX: i32 = (1 << 23) - 1;
+
+const DITHER_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum AudioSamples {
     MonoI8(Vec<i8>),
     StereoI8(Vec<[i8; 2]>),
@@ -9,6 +15,12 @@ pub enum AudioSamples {
     StereoI16(Vec<[i16; 2]>),
     MonoI32(Vec<i32>),
     StereoI32(Vec<[i32; 2]>),
+    // 3 bytes per sample in the file, sign extended to i32 (so within I24_MIN..=I24_MAX)
+    MonoI24(Vec<i32>),
+    StereoI24(Vec<[i32; 2]>),
+    // Data of any format but PCM (float, ADPCM...) as the raw bytes of the data chunk,
+    // so such files can still be loaded and saved back - the samples cannot be processed
+    Encoded(Vec<u8>),
 }
 
 impl AudioSamples {
@@ -19,14 +31,16 @@ impl AudioSamples {
     ) -> Result<AudioSamples, WavError> {
         let data_field: AudioSamples = match (num_channels, bits_per_sample) {
             // 8 bits per sample
+            // Unlike the wider ones these are unsigned, with silence at 128 - flipping the top bit
+            // moves them to the signed range the other variants use (0 -> -128, 128 -> 0)
             (1, 8) => {
-                let samples = audio_data.iter().map(|&b| b as i8).collect();
+                let samples = audio_data.iter().map(|&b| (b ^ 0x80) as i8).collect();
                 AudioSamples::MonoI8(samples)
             }
             (2, 8) => {
                 let samples = audio_data
                     .chunks_exact(2)
-                    .map(|c| [i8::from_le_bytes([c[0]]), i8::from_le_bytes([c[1]])])
+                    .map(|c| [(c[0] ^ 0x80) as i8, (c[1] ^ 0x80) as i8])
                     .collect();
                 AudioSamples::StereoI8(samples)
             }
@@ -70,6 +84,27 @@ impl AudioSamples {
                     .collect();
                 AudioSamples::StereoI32(samples)
             }
+            // 24 bits per sample
+            // The 3 bytes go into the top of an i32, shifting them back down extends the sign
+            (1, 24) => {
+                let samples = audio_data
+                    .chunks_exact(3)
+                    .map(|c| i32::from_le_bytes([0, c[0], c[1], c[2]]) >> 8)
+                    .collect();
+                AudioSamples::MonoI24(samples)
+            }
+            (2, 24) => {
+                let samples = audio_data
+                    .chunks_exact(6)
+                    .map(|c| {
+                        [
+                            i32::from_le_bytes([0, c[0], c[1], c[2]]) >> 8,
+                            i32::from_le_bytes([0, c[3], c[4], c[5]]) >> 8,
+                        ]
+                    })
+                    .collect();
+                AudioSamples::StereoI24(samples)
+            }
             // Unsupported sample size
             _ => return Err(WavError::InvalidWAudioFormat),
         };
@@ -78,10 +113,10 @@ impl AudioSamples {
 
     pub fn to_le_bytes_vector(&self) -> Vec<u8> {
         match self {
-            // 8 bit per sample
-            AudioSamples::MonoI8(v) => v.iter().map(|&b| b as u8).collect(),
+            // 8 bit per sample, back to unsigned (see from_le_bytes)
+            AudioSamples::MonoI8(v) => v.iter().map(|&b| b as u8 ^ 0x80).collect(),
             AudioSamples::StereoI8(v) => {
-                v.iter().flat_map(|c| c.iter().map(|&b| b as u8)).collect()
+                v.iter().flat_map(|c| c.iter().map(|&b| b as u8 ^ 0x80)).collect()
             }
             // 16 bit per sample
             AudioSamples::MonoI16(v) => v.iter().flat_map(|&b| b.to_le_bytes()).collect(),
@@ -95,6 +130,176 @@ impl AudioSamples {
                 .iter()
                 .flat_map(|c| c.iter().flat_map(|&b| b.to_le_bytes()))
                 .collect(),
+            // 24 bit per sample, the lowest 3 bytes of each i32
+            AudioSamples::MonoI24(v) => v.iter().flat_map(|&b| i24_to_le_bytes(b)).collect(),
+            AudioSamples::StereoI24(v) => v
+                .iter()
+                .flat_map(|c| c.iter().flat_map(|&b| i24_to_le_bytes(b)))
+                .collect(),
+            AudioSamples::Encoded(v) => v.clone(),
+        }
+    }
+
+    pub fn append(&mut self, other: &AudioSamples) -> Result<(), WavError> {
+        match (self, other) {
+            (AudioSamples::MonoI8(a), AudioSamples::MonoI8(b)) => a.extend_from_slice(b),
+            (AudioSamples::StereoI8(a), AudioSamples::StereoI8(b)) => a.extend_from_slice(b),
+            (AudioSamples::MonoI16(a), AudioSamples::MonoI16(b)) => a.extend_from_slice(b),
+            (AudioSamples::StereoI16(a), AudioSamples::StereoI16(b)) => a.extend_from_slice(b),
+            (AudioSamples::MonoI32(a), AudioSamples::MonoI32(b)) => a.extend_from_slice(b),
+            (AudioSamples::StereoI32(a), AudioSamples::StereoI32(b)) => a.extend_from_slice(b),
+            (AudioSamples::MonoI24(a), AudioSamples::MonoI24(b)) => a.extend_from_slice(b),
+            (AudioSamples::StereoI24(a), AudioSamples::StereoI24(b)) => a.extend_from_slice(b),
+            _ => return Err(WavError::InvalidWAudioFormat),
+        }
+        Ok(())
+    }
+
+    pub fn reverse(&mut self) {
+        // Stereo frames are reversed as a whole, so the channels stay paired
+        match self {
+            AudioSamples::MonoI8(v) => v.reverse(),
+            AudioSamples::StereoI8(v) => v.reverse(),
+            AudioSamples::MonoI16(v) => v.reverse(),
+            AudioSamples::StereoI16(v) => v.reverse(),
+            AudioSamples::MonoI32(v) => v.reverse(),
+            AudioSamples::StereoI32(v) => v.reverse(),
+            AudioSamples::MonoI24(v) => v.reverse(),
+            AudioSamples::StereoI24(v) => v.reverse(),
+            // The frames of encoded data are unknown, WavFile::reverse turns it away beforehand
+            AudioSamples::Encoded(_) => {}
+        }
+    }
+
+    pub fn invert_polarity(&mut self, channel: Option<usize>) {
+        // Negates the samples of one channel (or all of them for None), mono has only channel 0
+        // saturating_neg maps MIN to MAX, plain negation would overflow there
+        fn invert_stereo<T>(frames: &mut [[T; 2]], channel: Option<usize>, negate: fn(&T) -> T) {
+            for frame in frames.iter_mut() {
+                for (index, sample) in frame.iter_mut().enumerate() {
+                    if channel.is_none_or(|channel| channel == index) {
+                        *sample = negate(sample);
+                    }
+                }
+            }
+        }
+
+        match self {
+            AudioSamples::MonoI8(v) => v.iter_mut().for_each(|b| *b = b.saturating_neg()),
+            AudioSamples::StereoI8(v) => invert_stereo(v, channel, |b| b.saturating_neg()),
+            AudioSamples::MonoI16(v) => v.iter_mut().for_each(|b| *b = b.saturating_neg()),
+            AudioSamples::StereoI16(v) => invert_stereo(v, channel, |b| b.saturating_neg()),
+            AudioSamples::MonoI32(v) => v.iter_mut().for_each(|b| *b = b.saturating_neg()),
+            AudioSamples::StereoI32(v) => invert_stereo(v, channel, |b| b.saturating_neg()),
+            // Same for 24 bits, where the saturation has to be done by hand
+            AudioSamples::MonoI24(v) => v.iter_mut().for_each(|b| *b = (-*b).min(I24_MAX)),
+            AudioSamples::StereoI24(v) => invert_stereo(v, channel, |b| (-b).min(I24_MAX)),
+            AudioSamples::Encoded(_) => {}
+        }
+    }
+
+    pub fn full_scale(&self) -> f64 {
+        // The greatest absolute value a sample can take
+        // Taken from MIN rather than MAX, so that dividing by it always lands in [-1.0, 1.0]
+        match self {
+            AudioSamples::MonoI8(_) | AudioSamples::StereoI8(_) => -(i8::MIN as f64),
+            AudioSamples::MonoI16(_) | AudioSamples::StereoI16(_) => -(i16::MIN as f64),
+            AudioSamples::MonoI32(_) | AudioSamples::StereoI32(_) => -(i32::MIN as f64),
+            AudioSamples::MonoI24(_) | AudioSamples::StereoI24(_) => -(I24_MIN as f64),
+            AudioSamples::Encoded(_) => 1.0,
+        }
+    }
+
+    pub fn num_channels(&self) -> u16 {
+        match self {
+            AudioSamples::MonoI8(_)
+            | AudioSamples::MonoI16(_)
+            | AudioSamples::MonoI24(_)
+            | AudioSamples::MonoI32(_) => 1,
+            AudioSamples::StereoI8(_)
+            | AudioSamples::StereoI16(_)
+            | AudioSamples::StereoI24(_)
+            | AudioSamples::StereoI32(_) => 2,
+            // Only the fmt chunk of the file knows, 0 for unknown
+            AudioSamples::Encoded(_) => 0,
+        }
+    }
+
+    pub fn bits_per_sample(&self) -> u16 {
+        match self {
+            AudioSamples::MonoI8(_) | AudioSamples::StereoI8(_) => 8,
+            AudioSamples::MonoI16(_) | AudioSamples::StereoI16(_) => 16,
+            AudioSamples::MonoI24(_) | AudioSamples::StereoI24(_) => 24,
+            AudioSamples::MonoI32(_) | AudioSamples::StereoI32(_) => 32,
+            AudioSamples::Encoded(_) => 0,
+        }
+    }
+
+    // Number of frames, a stereo frame holds both of its samples
+    pub fn len(&self) -> usize {
+        match self {
+            AudioSamples::MonoI8(v) => v.len(),
+            AudioSamples::StereoI8(v) => v.len(),
+            AudioSamples::MonoI16(v) => v.len(),
+            AudioSamples::StereoI16(v) => v.len(),
+            AudioSamples::MonoI32(v) => v.len(),
+            AudioSamples::StereoI32(v) => v.len(),
+            AudioSamples::MonoI24(v) => v.len(),
+            AudioSamples::StereoI24(v) => v.len(),
+            // Not countable without decoding, see WavFile::num_frames
+            AudioSamples::Encoded(_) => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn full_scale_for(bits_per_sample: u16) -> Result<f64, WavError> {
+        match bits_per_sample {
+            8 => Ok(-(i8::MIN as f64)),
+            16 => Ok(-(i16::MIN as f64)),
+            24 => Ok(-(I24_MIN as f64)),
+            32 => Ok(-(i32::MIN as f64)),
+            _ => Err(WavError::InvalidWAudioFormat),
+        }
+    }
+
+    pub fn iter_f64_interleaved(&self) -> impl Iterator<Item = f64> + '_ {
+        // Samples of all channels in the order they are stored in the file (L, R, L, R...),
+        // normalized to [-1.0, 1.0]
+        let full_scale = self.full_scale();
+        let samples: Box<dyn Iterator<Item = f64> + '_> = match self {
+            AudioSamples::MonoI8(v) => Box::new(v.iter().map(|&b| b as f64)),
+            AudioSamples::StereoI8(v) => Box::new(v.iter().flatten().map(|&b| b as f64)),
+            AudioSamples::MonoI16(v) => Box::new(v.iter().map(|&b| b as f64)),
+            AudioSamples::StereoI16(v) => Box::new(v.iter().flatten().map(|&b| b as f64)),
+            AudioSamples::MonoI32(v) => Box::new(v.iter().map(|&b| b as f64)),
+            AudioSamples::StereoI32(v) => Box::new(v.iter().flatten().map(|&b| b as f64)),
+            AudioSamples::MonoI24(v) => Box::new(v.iter().map(|&b| b as f64)),
+            AudioSamples::StereoI24(v) => Box::new(v.iter().flatten().map(|&b| b as f64)),
+            AudioSamples::Encoded(_) => Box::new(std::iter::empty()),
+        };
+        samples.map(move |b| b / full_scale)
+    }
+
+    pub fn from_f64_interleaved_normalized(
+        data: &[f64],
+        num_channels: u16,
+        bits_per_sample: u16,
+    ) -> Result<AudioSamples, WavError> {
+        // Inverse of iter_f64_interleaved
+        let full_scale = Self::full_scale_for(bits_per_sample)?;
+        let scaled: Vec<f64> = data.iter().map(|&b| b * full_scale).collect();
+
+        match num_channels {
+            1 => Self::from_f64_mono(&scaled, bits_per_sample, false),
+            2 => {
+                let (left_channel, right_channel): (Vec<f64>, Vec<f64>) =
+                    scaled.chunks_exact(2).map(|c| (c[0], c[1])).unzip();
+                Self::from_f64_stereo(&left_channel, &right_channel, bits_per_sample, false)
+            }
+            _ => Err(WavError::InvalidWAudioFormat),
         }
     }
 
@@ -102,7 +307,9 @@ impl AudioSamples {
         let data = match self {
             AudioSamples::MonoI8(v) => v.iter().map(|&b| b as f64).collect(),
             AudioSamples::MonoI16(v) => v.iter().map(|&b| b as f64).collect(),
-            AudioSamples::MonoI32(v) => v.iter().map(|&b| b as f64).collect(),
+            AudioSamples::MonoI24(v) | AudioSamples::MonoI32(v) => {
+                v.iter().map(|&b| b as f64).collect()
+            }
             _ => return Err(WavError::InvalidWAudioFormat),
         };
         Ok(data)
@@ -118,7 +325,7 @@ impl AudioSamples {
                 v.iter().map(|pair| pair[0] as f64).collect(),
                 v.iter().map(|pair| pair[1] as f64).collect(),
             ),
-            AudioSamples::StereoI32(v) => (
+            AudioSamples::StereoI24(v) | AudioSamples::StereoI32(v) => (
                 v.iter().map(|pair| pair[0] as f64).collect(),
                 v.iter().map(|pair| pair[1] as f64).collect(),
             ),
@@ -127,20 +334,21 @@ impl AudioSamples {
         Ok(data)
     }
 
-    pub fn from_f64_mono(channel: &[f64], bits_per_sample: u16) -> Result<AudioSamples, WavError> {
+    pub fn from_f64_mono(
+        channel: &[f64],
+        bits_per_sample: u16,
+        dither: bool,
+    ) -> Result<AudioSamples, WavError> {
+        let channel: &[f64] = if dither {
+            &Self::tpdf_dither(channel, DITHER_SEED)
+        } else {
+            channel
+        };
         let data = match bits_per_sample {
-            8 => {
-                let samples = channel.iter().map(|&b| b.round() as i8).collect();
-                AudioSamples::MonoI8(samples)
-            }
-            16 => {
-                let samples = channel.iter().map(|&b| b.round() as i16).collect();
-                AudioSamples::MonoI16(samples)
-            }
-            32 => {
-                let samples = channel.iter().map(|&b| b.round() as i32).collect();
-                AudioSamples::MonoI32(samples)
-            }
+            8 => AudioSamples::MonoI8(round_samples(channel)),
+            16 => AudioSamples::MonoI16(round_samples(channel)),
+            24 => AudioSamples::MonoI24(round_samples_i24(channel)),
+            32 => AudioSamples::MonoI32(round_samples(channel)),
             _ => return Err(WavError::InvalidWAudioFormat),
         };
         Ok(data)
@@ -150,37 +358,99 @@ impl AudioSamples {
         left_channel: &[f64],
         right_channel: &[f64],
         bits_per_sample: u16,
+        dither: bool,
     ) -> Result<AudioSamples, WavError> {
-        let n = left_channel.len();
+        // A frame needs a sample of both channels, so they have to be equally long
+        if left_channel.len() != right_channel.len() {
+            return Err(WavError::ChannelLengthMismatch(
+                left_channel.len(),
+                right_channel.len(),
+            ));
+        }
+        // Both channels get different noise
+        let (left_channel, right_channel): (&[f64], &[f64]) = if dither {
+            (
+                &Self::tpdf_dither(left_channel, DITHER_SEED),
+                &Self::tpdf_dither(right_channel, !DITHER_SEED),
+            )
+        } else {
+            (left_channel, right_channel)
+        };
+        // Each channel is converted on its own, the loops stay simple enough to vectorize
+        fn pairs<T: Copy>(left_channel: Vec<T>, right_channel: Vec<T>) -> Vec<[T; 2]> {
+            left_channel.into_iter().zip(right_channel).map(|(l, r)| [l, r]).collect()
+        }
         let data = match bits_per_sample {
             8 => {
-                let mut samples = vec![[0_i8; 2]; n];
-                for i in 0..n {
-                    samples[i][0] = left_channel[i].round() as i8;
-                    samples[i][1] = right_channel[i].round() as i8;
-                }
-                AudioSamples::StereoI8(samples)
+                AudioSamples::StereoI8(pairs(round_samples(left_channel), round_samples(right_channel)))
             }
             16 => {
-                let mut samples = vec![[0_i16; 2]; n];
-                for i in 0..n {
-                    samples[i][0] = left_channel[i].round() as i16;
-                    samples[i][1] = right_channel[i].round() as i16;
-                }
-                AudioSamples::StereoI16(samples)
+                AudioSamples::StereoI16(pairs(round_samples(left_channel), round_samples(right_channel)))
             }
+            24 => AudioSamples::StereoI24(pairs(
+                round_samples_i24(left_channel),
+                round_samples_i24(right_channel),
+            )),
             32 => {
-                let mut samples = vec![[0_i32; 2]; n];
-                for i in 0..n {
-                    samples[i][0] = left_channel[i].round() as i32;
-                    samples[i][1] = right_channel[i].round() as i32;
-                }
-                AudioSamples::StereoI32(samples)
+                AudioSamples::StereoI32(pairs(round_samples(left_channel), round_samples(right_channel)))
             }
             _ => return Err(WavError::InvalidWAudioFormat),
         };
         Ok(data)
     }
+
+    fn tpdf_dither(channel: &[f64], seed: u64) -> Vec<f64> {
+        // Rounding to integers correlates the error with the signal (audible distortion),
+        // adding noise of triangular probability density (+-1 LSB) before it turns it into plain hiss
+        // The noise comes from a fixed seed xorshift generator, so the output is still reproducible
+        let mut state = seed;
+        let mut uniform = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 11) as f64 / (1_u64 << 53) as f64
+        };
+        channel.iter().map(|&b| b + uniform() - uniform()).collect()
+    }
+
+    // Same as the conversions above, but scaled to [-1.0, 1.0] regardless of the bit depth
+
+    pub fn to_f64_mono_normalized(&self) -> Result<Vec<f64>, WavError> {
+        let full_scale = self.full_scale();
+        let data = self.to_f64_mono()?;
+        Ok(data.iter().map(|&b| b / full_scale).collect())
+    }
+
+    pub fn to_f64_stereo_normalized(&self) -> Result<(Vec<f64>, Vec<f64>), WavError> {
+        let full_scale = self.full_scale();
+        let (left_channel, right_channel) = self.to_f64_stereo()?;
+        Ok((
+            left_channel.iter().map(|&b| b / full_scale).collect(),
+            right_channel.iter().map(|&b| b / full_scale).collect(),
+        ))
+    }
+
+    pub fn from_f64_mono_normalized(
+        channel: &[f64],
+        bits_per_sample: u16,
+        dither: bool,
+    ) -> Result<AudioSamples, WavError> {
+        let full_scale = Self::full_scale_for(bits_per_sample)?;
+        let scaled: Vec<f64> = channel.iter().map(|&b| b * full_scale).collect();
+        Self::from_f64_mono(&scaled, bits_per_sample, dither)
+    }
+
+    pub fn from_f64_stereo_normalized(
+        left_channel: &[f64],
+        right_channel: &[f64],
+        bits_per_sample: u16,
+        dither: bool,
+    ) -> Result<AudioSamples, WavError> {
+        let full_scale = Self::full_scale_for(bits_per_sample)?;
+        let left_scaled: Vec<f64> = left_channel.iter().map(|&b| b * full_scale).collect();
+        let right_scaled: Vec<f64> = right_channel.iter().map(|&b| b * full_scale).collect();
+        Self::from_f64_stereo(&left_scaled, &right_scaled, bits_per_sample, dither)
+    }
 }
 
 impl Display for AudioSamples {
@@ -192,6 +462,383 @@ impl Display for AudioSamples {
             AudioSamples::StereoI16(v) => write!(f, "StereoI16(len: {:?})", v),
             AudioSamples::MonoI32(v) => write!(f, "MonoI32(len: {:?})", v),
             AudioSamples::StereoI32(v) => write!(f, "StereoI32(len: {:?})", v),
+            AudioSamples::MonoI24(v) => write!(f, "MonoI24(len: {:?})", v),
+            AudioSamples::StereoI24(v) => write!(f, "StereoI24(len: {:?})", v),
+            AudioSamples::Encoded(v) => write!(f, "Encoded(bytes: {})", v.len()),
+        }
+    }
+}
+
+// Integer sample types the f64 conversions round to
+// The scalar rounding only needs from_f64, the chunked one of the simd feature the rest
+#[allow(dead_code)]
+pub(crate) trait SampleInt: Copy + Default {
+    const MIN_F64: f64;
+    const MAX_F64: f64;
+    // `as` saturates at the integer limits and turns NaN into 0
+    fn from_f64(value: f64) -> Self;
+    // Only called with values in range of Self
+    fn from_i32(value: i32) -> Self;
+}
+
+impl SampleInt for i8 {
+    const MIN_F64: f64 = i8::MIN as f64;
+    const MAX_F64: f64 = i8::MAX as f64;
+    fn from_f64(value: f64) -> Self {
+        value as i8
+    }
+    fn from_i32(value: i32) -> Self {
+        value as i8
+    }
+}
+
+impl SampleInt for i16 {
+    const MIN_F64: f64 = i16::MIN as f64;
+    const MAX_F64: f64 = i16::MAX as f64;
+    fn from_f64(value: f64) -> Self {
+        value as i16
+    }
+    fn from_i32(value: i32) -> Self {
+        value as i16
+    }
+}
+
+impl SampleInt for i32 {
+    const MIN_F64: f64 = i32::MIN as f64;
+    const MAX_F64: f64 = i32::MAX as f64;
+    fn from_f64(value: f64) -> Self {
+        value as i32
+    }
+    fn from_i32(value: i32) -> Self {
+        value
+    }
+}
+
+#[cfg(not(feature = "simd"))]
+fn round_samples<T: SampleInt>(channel: &[f64]) -> Vec<T> {
+    round_samples_scalar(channel)
+}
+
+#[cfg(feature = "simd")]
+fn round_samples<T: SampleInt>(channel: &[f64]) -> Vec<T> {
+    // Same result as round_samples_scalar, but in fixed size chunks without any branches,
+    // which the compiler turns into SIMD instructions
+    // f64::round has no SIMD instruction on plain x86_64, so it is done by hand: clamping keeps
+    // the value in range of i32, the cast truncates it and a leftover of at least a half
+    // rounds it away from 0, exactly like round does
+    const LANES: usize = 8;
+
+    fn round_sample<T: SampleInt>(value: f64) -> T {
+        let value = value.clamp(T::MIN_F64, T::MAX_F64);
+        let truncated = value as i32;
+        let fraction = value - truncated as f64;
+        T::from_i32(truncated + (fraction >= 0.5) as i32 - (fraction <= -0.5) as i32)
+    }
+
+    let mut output: Vec<T> = vec![T::default(); channel.len()];
+    let mut input_chunks = channel.chunks_exact(LANES);
+    let mut output_chunks = output.chunks_exact_mut(LANES);
+    for (input, output) in (&mut input_chunks).zip(&mut output_chunks) {
+        for i in 0..LANES {
+            output[i] = round_sample(input[i]);
+        }
+    }
+    for (input, output) in input_chunks.remainder().iter().zip(output_chunks.into_remainder()) {
+        *output = round_sample(*input);
+    }
+    output
+}
+
+fn round_samples_i24(channel: &[f64]) -> Vec<i32> {
+    // Rounded like the i32 ones, then saturated at the 24-bit limits
+    round_samples::<i32>(channel)
+        .into_iter()
+        .map(|b| b.clamp(I24_MIN, I24_MAX))
+        .collect()
+}
+
+fn i24_to_le_bytes(value: i32) -> [u8; 3] {
+    let [b0, b1, b2, _] = value.to_le_bytes();
+    [b0, b1, b2]
+}
+
+#[cfg(any(test, not(feature = "simd")))]
+pub(crate) fn round_samples_scalar<T: SampleInt>(channel: &[f64]) -> Vec<T> {
+    // Reference for the chunked version above, one sample at a time
+    channel.iter().map(|&b| T::from_f64(b.round())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn twenty_four_bit_samples_take_three_bytes() {
+        let bytes = [
+            0x01, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0x00, 0x00, 0x80,
+        ];
+        let samples = AudioSamples::from_le_bytes(&bytes, 1, 24).unwrap();
+        assert_eq!(
+            samples,
+            AudioSamples::MonoI24(vec![1, -1, I24_MAX, I24_MIN])
+        );
+        assert_eq!(samples.to_le_bytes_vector(), bytes);
+        assert_eq!(samples.full_scale(), 8388608.0);
+
+        let samples = AudioSamples::from_le_bytes(&bytes, 2, 24).unwrap();
+        assert_eq!(
+            samples,
+            AudioSamples::StereoI24(vec![[1, -1], [I24_MAX, I24_MIN]])
+        );
+        assert_eq!(samples.to_le_bytes_vector(), bytes);
+        assert_eq!((samples.num_channels(), samples.bits_per_sample()), (2, 24));
+    }
+
+    #[test]
+    fn twenty_four_bit_samples_saturate() {
+        let samples = AudioSamples::from_f64_mono(&[1e7, -1e7, 2.6], 24, false).unwrap();
+        assert_eq!(samples, AudioSamples::MonoI24(vec![I24_MAX, I24_MIN, 3]));
+
+        let samples = AudioSamples::from_f64_stereo(&[1e7, 0.4], &[-1e7, -2.6], 24, false).unwrap();
+        assert_eq!(
+            samples,
+            AudioSamples::StereoI24(vec![[I24_MAX, I24_MIN], [0, -3]])
+        );
+    }
+
+    fn all_variants() -> Vec<AudioSamples> {
+        vec![
+            AudioSamples::MonoI8(vec![-128, -1, 0, 1, 127]),
+            AudioSamples::StereoI8(vec![[-128, 127], [0, 5]]),
+            AudioSamples::MonoI16(vec![i16::MIN, -1, 0, 1, i16::MAX]),
+            AudioSamples::StereoI16(vec![[i16::MIN, i16::MAX], [0, 300]]),
+            AudioSamples::MonoI24(vec![I24_MIN, -1, 0, 1, I24_MAX]),
+            AudioSamples::StereoI24(vec![[I24_MIN, I24_MAX], [0, 70000]]),
+            AudioSamples::MonoI32(vec![i32::MIN, -1, 0, 1, i32::MAX]),
+            AudioSamples::StereoI32(vec![[i32::MIN, i32::MAX], [0, 1 << 20]]),
+        ]
+    }
+
+    #[test]
+    fn interleaved_f64_round_trips() {
+        let formats = [
+            (1, 8),
+            (2, 8),
+            (1, 16),
+            (2, 16),
+            (1, 24),
+            (2, 24),
+            (1, 32),
+            (2, 32),
+        ];
+        for (samples, (num_channels, bits_per_sample)) in all_variants().into_iter().zip(formats) {
+            let interleaved: Vec<f64> = samples.iter_f64_interleaved().collect();
+            assert!(interleaved.iter().all(|b| (-1.0..=1.0).contains(b)));
+
+            let rebuilt = AudioSamples::from_f64_interleaved_normalized(
+                &interleaved,
+                num_channels,
+                bits_per_sample,
+            )
+            .unwrap();
+            assert_eq!(rebuilt, samples);
         }
+        // Left and right alternate
+        let samples = AudioSamples::StereoI16(vec![[16384, -16384]]);
+        assert_eq!(
+            samples.iter_f64_interleaved().collect::<Vec<f64>>(),
+            vec![0.5, -0.5]
+        );
+    }
+
+    #[test]
+    fn normalized_conversions() {
+        let samples = AudioSamples::MonoI16(vec![i16::MAX, i16::MIN, 0]);
+        let normalized = samples.to_f64_mono_normalized().unwrap();
+        assert!((normalized[0] - 1.0).abs() < 1e-4);
+        assert_eq!(normalized[1], -1.0);
+        assert_eq!(normalized[2], 0.0);
+        assert_eq!(
+            AudioSamples::from_f64_mono_normalized(&normalized, 16, false).unwrap(),
+            samples
+        );
+
+        let samples = AudioSamples::StereoI16(vec![[i16::MAX, 0], [-8192, i16::MIN]]);
+        let (left_channel, right_channel) = samples.to_f64_stereo_normalized().unwrap();
+        assert_eq!(right_channel, vec![0.0, -1.0]);
+        assert_eq!(
+            AudioSamples::from_f64_stereo_normalized(&left_channel, &right_channel, 16, false)
+                .unwrap(),
+            samples
+        );
+
+        // Out of range values saturate instead of wrapping around
+        assert_eq!(
+            AudioSamples::from_f64_mono_normalized(&[1.5, -1.5], 8, false).unwrap(),
+            AudioSamples::MonoI8(vec![127, -128])
+        );
+        assert!(samples.to_f64_mono().is_err());
+        assert!(AudioSamples::MonoI8(vec![1]).to_f64_stereo().is_err());
+    }
+
+    #[test]
+    fn appending_needs_the_same_variant() {
+        let mut samples = AudioSamples::MonoI16(vec![1, 2]);
+        samples.append(&AudioSamples::MonoI16(vec![3])).unwrap();
+        assert_eq!(samples, AudioSamples::MonoI16(vec![1, 2, 3]));
+        assert!(
+            samples
+                .append(&AudioSamples::StereoI16(vec![[1, 1]]))
+                .is_err()
+        );
+        assert!(samples.append(&AudioSamples::MonoI32(vec![1])).is_err());
+    }
+
+    #[test]
+    fn eight_bit_samples_are_unsigned_in_the_file() {
+        let samples = AudioSamples::from_le_bytes(&[0x80, 0xFF, 0x00, 0x81], 1, 8).unwrap();
+        assert_eq!(samples, AudioSamples::MonoI8(vec![0, 127, -128, 1]));
+        assert_eq!(samples.to_le_bytes_vector(), vec![0x80, 0xFF, 0x00, 0x81]);
+
+        let samples = AudioSamples::from_le_bytes(&[0x80, 0x00, 0xFF, 0x7F], 2, 8).unwrap();
+        assert_eq!(samples, AudioSamples::StereoI8(vec![[0, -128], [127, -1]]));
+        assert_eq!(samples.to_le_bytes_vector(), vec![0x80, 0x00, 0xFF, 0x7F]);
+
+        let silence = AudioSamples::from_f64_mono(&[0.0; 3], 8, false).unwrap();
+        assert_eq!(silence.to_le_bytes_vector(), vec![0x80; 3]);
+    }
+
+    #[test]
+    fn dither_averages_out() {
+        // 0.3 LSB can only be rounded down, dither lets it show up in the average instead
+        let channel = vec![0.3; 20000];
+        let plain = AudioSamples::from_f64_mono(&channel, 16, false).unwrap();
+        assert_eq!(plain, AudioSamples::MonoI16(vec![0; 20000]));
+
+        let dithered = AudioSamples::from_f64_mono(&channel, 16, true).unwrap();
+        let values = dithered.to_f64_mono().unwrap();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        assert!((mean - 0.3).abs() < 0.05, "mean {mean}");
+        assert!(values.iter().all(|b| b.abs() <= 2.0));
+
+        // Both conversions are reproducible, the noise comes from a fixed seed
+        assert_eq!(
+            AudioSamples::from_f64_mono(&channel, 16, false).unwrap(),
+            plain
+        );
+        assert_eq!(
+            AudioSamples::from_f64_mono(&channel, 16, true).unwrap(),
+            dithered
+        );
+
+        // The right channel gets different noise than the left one
+        let stereo = AudioSamples::from_f64_stereo(&channel, &channel, 16, true).unwrap();
+        let (left_channel, right_channel) = stereo.to_f64_stereo().unwrap();
+        assert_eq!(left_channel, values);
+        assert_ne!(left_channel, right_channel);
+    }
+
+    #[test]
+    fn stereo_channels_of_different_lengths_are_rejected() {
+        assert!(matches!(
+            AudioSamples::from_f64_stereo(&[0.0; 4], &[0.0; 3], 16, false),
+            Err(WavError::ChannelLengthMismatch(4, 3))
+        ));
+        assert!(matches!(
+            AudioSamples::from_f64_stereo(&[0.0; 3], &[0.0; 4], 16, false),
+            Err(WavError::ChannelLengthMismatch(3, 4))
+        ));
+        assert!(matches!(
+            AudioSamples::from_f64_stereo_normalized(&[0.5], &[], 16, true),
+            Err(WavError::ChannelLengthMismatch(1, 0))
+        ));
+    }
+
+    #[test]
+    fn inverting_polarity_saturates() {
+        let mut samples = AudioSamples::MonoI16(vec![i16::MIN, -5, 0, i16::MAX]);
+        samples.invert_polarity(None);
+        assert_eq!(
+            samples,
+            AudioSamples::MonoI16(vec![i16::MAX, 5, 0, -i16::MAX])
+        );
+
+        let original = AudioSamples::StereoI16(vec![[100, -200], [300, -400]]);
+        let mut samples = original.clone();
+        samples.invert_polarity(Some(1));
+        assert_eq!(
+            samples,
+            AudioSamples::StereoI16(vec![[100, 200], [300, 400]])
+        );
+        samples.invert_polarity(Some(1));
+        assert_eq!(samples, original);
+    }
+
+    #[test]
+    fn rounding_matches_the_scalar_reference() {
+        // With the simd feature round_samples is the chunked version, otherwise this is trivial
+        let channel: Vec<f64> = [
+            0.5,
+            -0.5,
+            1.5,
+            -1.5,
+            2.4999,
+            -2.5001,
+            1e12,
+            -1e12,
+            f64::NAN,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            127.5,
+            -128.5,
+            32767.5,
+            -32768.5,
+        ]
+        .iter()
+        .copied()
+        .chain((0..100).map(|i| (i as f64 - 50.0) * 0.37))
+        .collect();
+        assert_eq!(
+            round_samples::<i8>(&channel),
+            round_samples_scalar::<i8>(&channel)
+        );
+        assert_eq!(
+            round_samples::<i16>(&channel),
+            round_samples_scalar::<i16>(&channel)
+        );
+        assert_eq!(
+            round_samples::<i32>(&channel),
+            round_samples_scalar::<i32>(&channel)
+        );
+    }
+
+    #[test]
+    fn variants_report_their_format() {
+        let formats: Vec<(u16, u16)> = all_variants()
+            .iter()
+            .map(|samples| (samples.num_channels(), samples.bits_per_sample()))
+            .collect();
+        assert_eq!(
+            formats,
+            vec![
+                (1, 8),
+                (2, 8),
+                (1, 16),
+                (2, 16),
+                (1, 24),
+                (2, 24),
+                (1, 32),
+                (2, 32),
+            ]
+        );
+    }
+
+    #[test]
+    fn lengths_count_frames() {
+        let lengths: Vec<usize> = all_variants().iter().map(AudioSamples::len).collect();
+        assert_eq!(lengths, vec![5, 2, 5, 2, 5, 2, 5, 2]);
+        assert!(AudioSamples::MonoI16(Vec::new()).is_empty());
+        // Encoded bytes are not frames, WavFile::num_frames asks the fact chunk instead
+        assert!(AudioSamples::Encoded(vec![0; 8]).is_empty());
     }
 }
@@ -0,0 +1,149 @@
+use std::f64::consts::PI;
+
+// Second order IIR filter coefficients, already divided by a0
+// Formulas taken from the Audio EQ Cookbook:
+// https://webaudio.github.io/Audio-EQ-Cookbook/audio-eq-cookbook.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiquadCoeffs {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+
+impl BiquadCoeffs {
+    fn normalized(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> BiquadCoeffs {
+        BiquadCoeffs {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    // Q of 1/sqrt(2) gives the flattest (Butterworth) response
+    pub fn low_pass(cutoff_hz: f64, sample_rate: u32, q: f64) -> BiquadCoeffs {
+        let w0 = 2. * PI * cutoff_hz / sample_rate as f64;
+        let alpha = f64::sin(w0) / (2. * q);
+        let cos_w0 = f64::cos(w0);
+
+        Self::normalized(
+            (1. - cos_w0) / 2.,
+            1. - cos_w0,
+            (1. - cos_w0) / 2.,
+            1. + alpha,
+            -2. * cos_w0,
+            1. - alpha,
+        )
+    }
+
+    pub fn high_pass(cutoff_hz: f64, sample_rate: u32, q: f64) -> BiquadCoeffs {
+        let w0 = 2. * PI * cutoff_hz / sample_rate as f64;
+        let alpha = f64::sin(w0) / (2. * q);
+        let cos_w0 = f64::cos(w0);
+
+        Self::normalized(
+            (1. + cos_w0) / 2.,
+            -(1. + cos_w0),
+            (1. + cos_w0) / 2.,
+            1. + alpha,
+            -2. * cos_w0,
+            1. - alpha,
+        )
+    }
+
+    // Constant 0 dB gain at the center frequency, Q controls the width of the band
+    pub fn band_pass(center_hz: f64, sample_rate: u32, q: f64) -> BiquadCoeffs {
+        let w0 = 2. * PI * center_hz / sample_rate as f64;
+        let alpha = f64::sin(w0) / (2. * q);
+        let cos_w0 = f64::cos(w0);
+
+        Self::normalized(alpha, 0., -alpha, 1. + alpha, -2. * cos_w0, 1. - alpha)
+    }
+}
+
+pub fn biquad_filter(samples: &[f64], coeffs: &BiquadCoeffs) -> Vec<f64> {
+    // Direct form I:
+    // y[n] = b0 * x[n] + b1 * x[n-1] + b2 * x[n-2] - a1 * y[n-1] - a2 * y[n-2]
+    let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+
+    samples
+        .iter()
+        .map(|&x| {
+            let y =
+                coeffs.b0 * x + coeffs.b1 * x1 + coeffs.b2 * x2 - coeffs.a1 * y1 - coeffs.a2 * y2;
+            (x2, x1) = (x1, x);
+            (y2, y1) = (y1, y);
+            y
+        })
+        .collect()
+}
+
+pub fn filtfilt(samples: &[f64], coeffs: &BiquadCoeffs) -> Vec<f64> {
+    // Zero-phase filtering - the signal goes through the filter forwards and then backwards,
+    // so the phase shifts of both passes cancel out (and the magnitude response is squared)
+    let forward = biquad_filter(samples, coeffs);
+    let reversed: Vec<f64> = forward.into_iter().rev().collect();
+    let mut output = biquad_filter(&reversed, coeffs);
+    output.reverse();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steady_state_gain(coeffs: &BiquadCoeffs, frequency_hz: f64) -> f64 {
+        // Amplitude of a unit sine at 8 kHz once the filter settled, from its last 1000 samples
+        let input: Vec<f64> = (0..8000)
+            .map(|i| f64::sin(2. * PI * frequency_hz * i as f64 / 8000.0))
+            .collect();
+        let output = biquad_filter(&input, coeffs);
+        output[7000..]
+            .iter()
+            .fold(0.0, |peak: f64, y| peak.max(y.abs()))
+    }
+
+    #[test]
+    fn filters_pass_and_stop_the_right_bands() {
+        let low_pass = BiquadCoeffs::low_pass(500.0, 8000, 1.0 / 2f64.sqrt());
+        assert!((steady_state_gain(&low_pass, 50.0) - 1.0).abs() < 0.01);
+        assert!(steady_state_gain(&low_pass, 3000.0) < 0.05);
+        // Butterworth, -3 dB at the cutoff
+        assert!((steady_state_gain(&low_pass, 500.0) - 1.0 / 2f64.sqrt()).abs() < 0.01);
+
+        let high_pass = BiquadCoeffs::high_pass(500.0, 8000, 1.0 / 2f64.sqrt());
+        assert!(steady_state_gain(&high_pass, 50.0) < 0.02);
+        assert!((steady_state_gain(&high_pass, 3000.0) - 1.0).abs() < 0.01);
+
+        let band_pass = BiquadCoeffs::band_pass(1000.0, 8000, 5.0);
+        assert!((steady_state_gain(&band_pass, 1000.0) - 1.0).abs() < 0.01);
+        assert!(steady_state_gain(&band_pass, 100.0) < 0.05);
+        assert!(steady_state_gain(&band_pass, 3500.0) < 0.05);
+    }
+
+    #[test]
+    fn zero_phase_filtering_keeps_an_impulse_symmetric() {
+        let mut impulse = vec![0.0; 801];
+        impulse[400] = 1.0;
+        let coeffs = BiquadCoeffs::low_pass(300.0, 8000, 1.0 / 2f64.sqrt());
+
+        let output = filtfilt(&impulse, &coeffs);
+        for k in 1..400 {
+            assert!(
+                (output[400 + k] - output[400 - k]).abs() < 1e-9,
+                "offset {k}"
+            );
+        }
+        let peak = (0..output.len()).max_by(|&a, &b| output[a].total_cmp(&output[b]));
+        assert_eq!(peak, Some(400));
+
+        // A single pass delays the peak instead
+        let output = biquad_filter(&impulse, &coeffs);
+        let peak = (0..output.len()).max_by(|&a, &b| output[a].total_cmp(&output[b]));
+        assert!(peak.unwrap() > 400);
+        assert!(filtfilt(&[], &coeffs).is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+pub const CONFIG_FILE: &str = "denoiser.toml";
+
+// Settings of the TUI, read from denoiser.toml in the working directory
+// Every field is optional, the missing ones fall back to the defaults below
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub threshold: f64,
+    pub threshold_step: f64,
+    pub threshold_max: f64,
+    pub input_dir: PathBuf,
+    pub output_dir: PathBuf,
+    pub keys: KeyBindings,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub play: char,
+    pub quit: char,
+    pub toggle: char,
+    pub redenoise: char,
+    pub save: char,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            threshold: 0.01,
+            threshold_step: 0.01,
+            threshold_max: 0.1,
+            input_dir: PathBuf::from("data"),
+            output_dir: PathBuf::from("data").join("denoised"),
+            keys: KeyBindings::default(),
+        }
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            play: 'p',
+            quit: 'q',
+            toggle: 'c',
+            redenoise: 'r',
+            save: 's',
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> io::Result<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid config '{}': {}", path.display(), e),
+            )
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_full_config() {
+        let config = Config::parse(
+            r#"
+            threshold = 0.05
+            threshold_step = 0.005
+            threshold_max = 0.2
+            input_dir = "recordings"
+            output_dir = "clean"
+
+            [keys]
+            play = "x"
+            quit = "e"
+            toggle = "t"
+            redenoise = "d"
+            save = "w"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.threshold, 0.05);
+        assert_eq!(config.threshold_step, 0.005);
+        assert_eq!(config.threshold_max, 0.2);
+        assert_eq!(config.input_dir, PathBuf::from("recordings"));
+        assert_eq!(config.output_dir, PathBuf::from("clean"));
+        assert_eq!(
+            config.keys,
+            KeyBindings {
+                play: 'x',
+                quit: 'e',
+                toggle: 't',
+                redenoise: 'd',
+                save: 'w',
+            }
+        );
+    }
+
+    #[test]
+    fn missing_fields_use_the_defaults() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+
+        let config = Config::parse("threshold = 0.03\n[keys]\nplay = \"o\"").unwrap();
+        assert_eq!(config.threshold, 0.03);
+        assert_eq!(config.threshold_max, Config::default().threshold_max);
+        assert_eq!(config.keys.play, 'o');
+        assert_eq!(config.keys.quit, 'q');
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        assert!(Config::parse("threshold = \"high\"").is_err());
+        assert!(Config::parse("[keys]\nplay = \"long\"").is_err());
+        assert_eq!(
+            Config::load(Path::new("no/such/denoiser.toml")).unwrap(),
+            Config::default()
+        );
+    }
+}
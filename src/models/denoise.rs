@@ -0,0 +1,1109 @@
+use crate::models::fft::{
+    bin_to_hz, fft_bluestein, fft_real_zero_padded, ifft, ifft_bluestein, magnitude_spectrum_db,
+};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+// Spectrum of a single channel, before and after the threshold was applied
+#[derive(Debug, Clone)]
+pub struct ChannelSpectrum {
+    pub magnitudes_before: Vec<f64>,
+    pub magnitudes_after: Vec<f64>,
+    pub zeroed_bins: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DenoiseReport {
+    pub channels: Vec<ChannelSpectrum>,
+}
+
+impl DenoiseReport {
+    pub fn zeroed_bins(&self) -> usize {
+        self.channels.iter().map(|c| c.zeroed_bins).sum()
+    }
+}
+
+// How the length of the signal is handled around the FFT
+//
+// Zero padding to a power of 2 does not improve the frequency resolution, which is set by the
+// length of the actual signal - the extra bins only interpolate between the real ones
+// The padded part is not silent after denoising, zeroing bins spreads some of the signal into it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FftLength {
+    // Padded to the next power of 2, the output is cut back to the original length
+    Truncated,
+    // Padded to the next power of 2, the output keeps the padded length
+    Padded,
+    // No padding at all, uses the (slower) Bluestein FFT
+    Exact,
+}
+
+pub fn denoise_signal(samples: &[f64], treshold_percentage: f64) -> Vec<f64> {
+    let (output, _) =
+        denoise_signal_with_spectrum(samples, treshold_percentage, FftLength::Truncated);
+    output
+}
+
+pub fn denoise_signal_padded(samples: &[f64], treshold_percentage: f64) -> Vec<f64> {
+    // Same as above, but the output is as long as the padded signal
+    let (output, _) = denoise_signal_with_spectrum(samples, treshold_percentage, FftLength::Padded);
+    output
+}
+
+// Window applied to the whole signal before a single FFT, see denoise_signal_windowed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowKind {
+    // Goes all the way down to 0 at the edges, the least leakage
+    Hann,
+    // Stops at 0.08, a bit more leakage but a wider part of the signal gets windowed
+    Hamming,
+}
+
+impl WindowKind {
+    fn coefficients(&self, length: usize) -> Vec<f64> {
+        // Symmetric variant, both the first and the last sample sit at the edge of the window
+        if length < 2 {
+            return vec![1.0; length];
+        }
+        let (a0, a1) = match self {
+            WindowKind::Hann => (0.5, 0.5),
+            WindowKind::Hamming => (0.54, 0.46),
+        };
+        (0..length)
+            .map(|i| a0 - a1 * f64::cos(2. * PI * i as f64 / (length - 1) as f64))
+            .collect()
+    }
+}
+
+// Below this the window is not divided back out, it would blow up whatever noise was left there
+const WINDOW_FLOOR: f64 = 0.3;
+
+pub fn denoise_signal_windowed(
+    samples: &[f64],
+    treshold_percentage: f64,
+    window: WindowKind,
+) -> Vec<f64> {
+    // The file does not start and end at the same point of a period, so the FFT sees a jump
+    // at its boundaries, which leaks into every bin and gets through the threshold
+    // Tapering the signal with a window before the FFT gets rid of the jump,
+    // afterwards the window is divided back out
+    // Near the edges, where the window is too close to 0, the unwindowed result is used instead
+    let coefficients = window.coefficients(samples.len());
+    let windowed: Vec<f64> = samples
+        .iter()
+        .zip(coefficients.iter())
+        .map(|(x, w)| x * w)
+        .collect();
+    let denoised_windowed = denoise_signal(&windowed, treshold_percentage);
+
+    let needs_fallback = coefficients.iter().any(|&w| w < WINDOW_FLOOR);
+    let denoised_plain = if needs_fallback {
+        denoise_signal(samples, treshold_percentage)
+    } else {
+        Vec::new()
+    };
+
+    denoised_windowed
+        .iter()
+        .zip(coefficients.iter())
+        .enumerate()
+        .map(|(i, (x, &w))| {
+            if w < WINDOW_FLOOR {
+                denoised_plain[i]
+            } else {
+                x / w
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn denoise_signal_with_spectrum(
+    samples: &[f64],
+    treshold_percentage: f64,
+    length: FftLength,
+) -> (Vec<f64>, ChannelSpectrum) {
+    // Denoising below applies the low-pass-filter using FFT
+    // It naively zeros all the frequencies, whose amplitude is lesser than threshold
+    // Threshold itself is calculated as treshold_percentage * max_frequency_amplitude
+
+    let original_length = samples.len();
+    let (mut re, mut im) = if length == FftLength::Exact {
+        fft_bluestein(samples, &vec![0.; original_length])
+    } else {
+        fft_real_zero_padded(samples)
+    };
+    let n = re.len();
+
+    // Unless exact length is used, the samples are padded to the nearest power of 2
+    // If we do not wish for silence at the end of new
+    // audiofile it has to be truncated after IFFT
+
+    // Compute the magnitudes of the signal in each frequency
+    let magnitudes: Vec<f64> = re
+        .iter()
+        .zip(im.iter())
+        .map(|(re, im)| (re.powi(2) + im.powi(2)).sqrt())
+        .collect();
+
+    // Find the greatest magnitude - it will be used to apply treshold accordingly
+    let max_magnitude = magnitudes.iter().fold(0.0_f64, |a, &b| a.max(b));
+
+    // Calculate the lower threshold to apply the low-pass-filter
+    // by zeroing frequencies below the threshold
+    let treshold = treshold_percentage * max_magnitude;
+
+    let mut magnitudes_after = magnitudes.clone();
+    let mut zeroed_bins = 0;
+
+    for i in 0..n {
+        if magnitudes[i] < treshold {
+            re[i] = 0.0;
+            im[i] = 0.0;
+            magnitudes_after[i] = 0.0;
+            zeroed_bins += 1;
+        }
+    }
+
+    // Truncate IFFT output, unless the padding is meant to be kept
+    let (re_denoised, _) = if length == FftLength::Exact {
+        ifft_bluestein(&re, &im)
+    } else {
+        ifft(&re, &im)
+    };
+    let output = match length {
+        FftLength::Padded => re_denoised,
+        FftLength::Truncated | FftLength::Exact => re_denoised[..original_length].to_vec(),
+    };
+
+    let spectrum = ChannelSpectrum {
+        magnitudes_before: magnitudes,
+        magnitudes_after,
+        zeroed_bins,
+    };
+
+    (output, spectrum)
+}
+
+pub fn stft_denoise(samples: &[f64], frame_size: usize, treshold_percentage: f64) -> Vec<f64> {
+    // Short-time denoising - the signal is cut into overlapping frames, each one is denoised
+    // on its own and they are added back together, so the threshold follows the spectrum
+    // as it changes in time instead of using one for the whole file
+    // Frame sizes that are powers of 2 avoid padding every frame
+    let frames: Vec<Vec<f64>> = stft_frames(samples, frame_size)
+        .iter()
+        .map(|frame| denoise_signal(frame, treshold_percentage))
+        .collect();
+    overlap_add(&frames, samples.len())
+}
+
+#[cfg(feature = "rayon")]
+pub fn par_stft(samples: &[f64], frame_size: usize, treshold_percentage: f64) -> Vec<f64> {
+    // Same as stft_denoise, but the frames are denoised in parallel
+    use rayon::prelude::*;
+
+    let frames: Vec<Vec<f64>> = stft_frames(samples, frame_size)
+        .par_iter()
+        .map(|frame| denoise_signal(frame, treshold_percentage))
+        .collect();
+    overlap_add(&frames, samples.len())
+}
+
+fn hann_window(frame_size: usize) -> Vec<f64> {
+    // Periodic variant (divided by frame_size, not frame_size - 1), which overlaps evenly
+    (0..frame_size)
+        .map(|i| 0.5 - 0.5 * f64::cos(2. * PI * i as f64 / frame_size as f64))
+        .collect()
+}
+
+pub fn cola_factor(window: &[f64], hop: usize) -> f64 {
+    // Sum of the windows shifted by hop at any single sample (constant overlap-add)
+    // The overlap-add output is this many times louder than the input, so it gets divided out
+    // For the periodic Hann window at 50% hop it is exactly 1
+    let hop = hop.max(1);
+    window.iter().step_by(hop).sum()
+}
+
+pub fn spectrogram(samples: &[f64], window_size: usize, hop: usize) -> Vec<Vec<f64>> {
+    // Magnitude spectrum in dB (see fft::magnitude_spectrum_db) of every Hann windowed frame,
+    // frames start hop samples apart, the last one is padded with silence
+    // Window sizes that are powers of 2 avoid padding every frame, which would add more bins
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let window_size = window_size.max(1);
+    let hop = hop.max(1);
+    let window = hann_window(window_size);
+
+    let count = samples.len().saturating_sub(window_size).div_ceil(hop) + 1;
+    (0..count)
+        .map(|k| {
+            let frame: Vec<f64> = (0..window_size)
+                .map(|i| samples.get(k * hop + i).unwrap_or(&0.0) * window[i])
+                .collect();
+            magnitude_spectrum_db(&frame)
+        })
+        .collect()
+}
+
+fn stft_frames(samples: &[f64], frame_size: usize) -> Vec<Vec<f64>> {
+    // Windows half a frame apart, so every sample ends up in two frames
+    // The signal is padded by half a frame at the start, so that this holds for the first samples too
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let frame_size = frame_size.max(2) / 2 * 2;
+    let hop = frame_size / 2;
+    let window = hann_window(frame_size);
+
+    // Every sample has to be covered by two frames
+    let count = (hop + samples.len() - 1) / hop + 1;
+    let mut padded = vec![0.0; hop];
+    padded.extend_from_slice(samples);
+    padded.resize((count - 1) * hop + frame_size, 0.0);
+
+    (0..count)
+        .map(|k| {
+            padded[k * hop..k * hop + frame_size]
+                .iter()
+                .zip(window.iter())
+                .map(|(x, w)| x * w)
+                .collect()
+        })
+        .collect()
+}
+
+fn overlap_add(frames: &[Vec<f64>], length: usize) -> Vec<f64> {
+    // Inverse of stft_frames, the padding at both ends is cut off
+    // and the gain of the overlapping windows is compensated
+    let Some(frame_size) = frames.first().map(|frame| frame.len()) else {
+        return Vec::new();
+    };
+    let hop = frame_size / 2;
+    let gain = cola_factor(&hann_window(frame_size), hop);
+
+    let mut output = vec![0.0; (frames.len() - 1) * hop + frame_size];
+    for (k, frame) in frames.iter().enumerate() {
+        for (i, x) in frame.iter().enumerate() {
+            output[k * hop + i] += x;
+        }
+    }
+    output[hop..hop + length].iter().map(|x| x / gain).collect()
+}
+
+pub fn moving_average(samples: &[f64], window: usize) -> Vec<f64> {
+    // Mean of the window centered on every sample, near the edges the window shrinks
+    let half = window / 2;
+    (0..samples.len())
+        .map(|i| {
+            let neighbours = &samples[i.saturating_sub(half)..(i + half + 1).min(samples.len())];
+            neighbours.iter().sum::<f64>() / neighbours.len() as f64
+        })
+        .collect()
+}
+
+pub fn median_filter(samples: &[f64], window: usize) -> Vec<f64> {
+    // Same as above with the median instead of the mean, which ignores short spikes entirely
+    let half = window / 2;
+    (0..samples.len())
+        .map(|i| {
+            let mut neighbours =
+                samples[i.saturating_sub(half)..(i + half + 1).min(samples.len())].to_vec();
+            neighbours.sort_by(f64::total_cmp);
+            let middle = neighbours.len() / 2;
+            if neighbours.len().is_multiple_of(2) {
+                (neighbours[middle - 1] + neighbours[middle]) / 2.0
+            } else {
+                neighbours[middle]
+            }
+        })
+        .collect()
+}
+
+pub fn sliding_minimum(samples: &[f64], before: usize, after: usize) -> Vec<f64> {
+    // Minimum of the `before` samples preceding every sample, itself and the `after` following it
+    // The deque holds indices of the window with increasing values, so its front is the minimum
+    // and every index goes in and out once - long windows cost no more than short ones
+    let mut window: VecDeque<usize> = VecDeque::new();
+    let push = |window: &mut VecDeque<usize>, next: usize| {
+        while window.back().is_some_and(|&j| samples[j] >= samples[next]) {
+            window.pop_back();
+        }
+        window.push_back(next);
+    };
+    for next in 0..after.min(samples.len()) {
+        push(&mut window, next);
+    }
+
+    (0..samples.len())
+        .map(|i| {
+            if i + after < samples.len() {
+                push(&mut window, i + after);
+            }
+            while window.front().is_some_and(|&j| j + before < i) {
+                window.pop_front();
+            }
+            samples[window[0]]
+        })
+        .collect()
+}
+
+pub fn declick(samples: &[f64], sensitivity: f64) -> Vec<f64> {
+    // Clicks are samples bending away from their neighbours far more than the signal does
+    // The bend is measured with the second difference x[i-1] - 2x[i] + x[i+1], and compared
+    // against its median over the whole signal - sensitivity 0.1 marks samples bending
+    // 10 times more than the typical one, higher values catch quieter clicks
+    let n = samples.len();
+    if n < 3 || sensitivity <= 0.0 {
+        return samples.to_vec();
+    }
+
+    let bends: Vec<f64> = (1..n - 1)
+        .map(|i| (samples[i - 1] - 2.0 * samples[i] + samples[i + 1]).abs())
+        .collect();
+    let mut sorted = bends.clone();
+    sorted.sort_by(f64::total_cmp);
+    let threshold = sorted[sorted.len() / 2] / sensitivity;
+
+    let is_click: Vec<bool> = (0..n)
+        .map(|i| i > 0 && i < n - 1 && bends[i - 1] > threshold)
+        .collect();
+
+    // Every click is replaced by a straight line between the closest good samples
+    let mut output = samples.to_vec();
+    let mut i = 0;
+    while i < n {
+        if !is_click[i] {
+            i += 1;
+            continue;
+        }
+        let start = i - 1;
+        let mut end = i;
+        while is_click[end] {
+            end += 1;
+        }
+        for (j, sample) in output[start + 1..end].iter_mut().enumerate() {
+            let t = (j + 1) as f64 / (end - start) as f64;
+            *sample = samples[start] * (1.0 - t) + samples[end] * t;
+        }
+        i = end;
+    }
+    output
+}
+
+pub fn noise_psd(noise: &[f64]) -> Vec<f64> {
+    // Power spectral density of a noise-only fragment, |X_k|^2 / length
+    // Dividing by the number of samples makes it independent of the fragment's length,
+    // so it can be compared against the spectrum of a longer signal
+    let (re, im) = fft_real_zero_padded(noise);
+    let length = noise.len().max(1) as f64;
+
+    re.iter()
+        .zip(im.iter())
+        .map(|(re, im)| (re.powi(2) + im.powi(2)) / length)
+        .collect()
+}
+
+pub fn wiener_filter(samples: &[f64], noise_psd: &[f64]) -> Vec<f64> {
+    // https://en.wikipedia.org/wiki/Wiener_filter
+    // Every bin is scaled by |X|^2 / (|X|^2 + N), where N is the expected noise power in it
+    // Both parts get the same real gain, so the phase is preserved
+
+    if noise_psd.is_empty() {
+        return samples.to_vec();
+    }
+
+    let original_length = samples.len();
+    let (mut re, mut im) = fft_real_zero_padded(samples);
+    let n = re.len();
+
+    for k in 0..n {
+        // The noise estimate may come from a fragment of different length,
+        // its bins are mapped proportionally onto ours
+        let psd_bin = k * noise_psd.len() / n;
+        let noise_power = noise_psd[psd_bin] * original_length as f64;
+
+        let power = re[k].powi(2) + im[k].powi(2);
+        let gain = if power + noise_power > 0.0 {
+            power / (power + noise_power)
+        } else {
+            1.0
+        };
+
+        re[k] *= gain;
+        im[k] *= gain;
+    }
+
+    let (re_filtered, _) = ifft(&re, &im);
+    re_filtered[..original_length].to_vec()
+}
+
+pub fn smooth_spectral_gate(
+    samples: &[f64],
+    treshold_percentage: f64,
+    smoothing_bins: usize,
+) -> Vec<f64> {
+    // Softer version of denoise_signal - the hard keep/zero leaves isolated bins that survive
+    // the threshold here and there, which ring as "musical noise"
+    // Instead every bin gets a gain r^4 / (1 + r^4), where r = magnitude / threshold,
+    // so bins at the threshold are halved and the ones well above it barely touched
+    // The gains are then averaged over smoothing_bins neighbouring bins, lone peaks get pulled down
+    // Both parts get the same real gain, so the phase is preserved
+
+    let original_length = samples.len();
+    let (mut re, mut im) = fft_real_zero_padded(samples);
+    let n = re.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let magnitudes: Vec<f64> = re
+        .iter()
+        .zip(im.iter())
+        .map(|(re, im)| (re.powi(2) + im.powi(2)).sqrt())
+        .collect();
+    let max_magnitude = magnitudes.iter().fold(0.0_f64, |a, &b| a.max(b));
+    let treshold = treshold_percentage * max_magnitude;
+    if treshold <= 0.0 {
+        return samples.to_vec();
+    }
+
+    // Only the half up to Nyquist is smoothed, the other one mirrors it
+    // (smoothing the whole spectrum would mix the highest and lowest frequencies at the edges)
+    let gains: Vec<f64> = magnitudes[..=n / 2]
+        .iter()
+        .map(|m| {
+            let r = (m / treshold).powi(4);
+            r / (1.0 + r)
+        })
+        .collect();
+    let gains = moving_average(&gains, smoothing_bins);
+
+    for k in 0..n {
+        let gain = gains[k.min(n - k)];
+        re[k] *= gain;
+        im[k] *= gain;
+    }
+
+    let (re_filtered, _) = ifft(&re, &im);
+    re_filtered[..original_length].to_vec()
+}
+
+// How much of its magnitude a bin below the threshold keeps in phase_mask (-20 dB)
+const PHASE_MASK_GAIN: f64 = 0.1;
+
+pub fn phase_mask(samples: &[f64], treshold_percentage: f64, seed: u64) -> Vec<f64> {
+    // Alternative to zeroing the bins below the threshold - they are turned down by
+    // PHASE_MASK_GAIN and get a random phase instead, so what is left of the noise
+    // becomes a quiet, even hiss rather than silence with a few stray tones in it
+    // The bins above the threshold are not touched at all
+    // The phases come from a xorshift generator started at the seed, same seed gives the same output
+    let original_length = samples.len();
+    let (mut re, mut im) = fft_real_zero_padded(samples);
+    let n = re.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let magnitudes: Vec<f64> = re
+        .iter()
+        .zip(im.iter())
+        .map(|(re, im)| (re.powi(2) + im.powi(2)).sqrt())
+        .collect();
+    let max_magnitude = magnitudes.iter().fold(0.0_f64, |a, &b| a.max(b));
+    let treshold = treshold_percentage * max_magnitude;
+
+    // xorshift never leaves 0, so that one seed is swapped for another
+    let mut state = if seed == 0 {
+        0x9E37_79B9_7F4A_7C15
+    } else {
+        seed
+    };
+    let mut random_phase = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        2. * PI * (state >> 11) as f64 / (1_u64 << 53) as f64
+    };
+
+    // Only the half up to Nyquist is drawn, the other one mirrors it as complex conjugates,
+    // otherwise the output would not be real
+    for k in 0..=n / 2 {
+        if magnitudes[k] >= treshold {
+            continue;
+        }
+        let residual = PHASE_MASK_GAIN * magnitudes[k];
+        let phase = random_phase();
+        if k == 0 || 2 * k == n {
+            // DC and Nyquist have no mirror, they have to stay real - only the sign is random
+            re[k] = residual * phase.cos().signum();
+            im[k] = 0.0;
+        } else {
+            re[k] = residual * phase.cos();
+            im[k] = residual * phase.sin();
+            re[n - k] = re[k];
+            im[n - k] = -im[k];
+        }
+    }
+
+    let (re_masked, _) = ifft(&re, &im);
+    re_masked[..original_length].to_vec()
+}
+
+pub fn a_weighting(frequency_hz: f64) -> f64 {
+    // Linear gain of the A-weighting curve, normalized to 1 at 1 kHz
+    // Roughly how loud a frequency sounds compared to others of the same amplitude -
+    // the ear is the most sensitive around 1-5 kHz and falls off towards both ends
+    fn response(f: f64) -> f64 {
+        let f2 = f * f;
+        let (a, b, c, d) = (
+            20.6_f64.powi(2),
+            107.7_f64.powi(2),
+            737.9_f64.powi(2),
+            12194.0_f64.powi(2),
+        );
+        d * f2 * f2 / ((f2 + a) * ((f2 + b) * (f2 + c)).sqrt() * (f2 + d))
+    }
+    response(frequency_hz.abs()) / response(1000.0)
+}
+
+pub fn denoise_signal_weighted(
+    samples: &[f64],
+    treshold_percentage: f64,
+    sample_rate: u32,
+    perceptual: bool,
+) -> Vec<f64> {
+    // Same as denoise_signal, but with perceptual set the magnitudes are compared to the threshold
+    // after A-weighting, so quiet bins the ear hardly hears (very low and very high ones)
+    // are removed more eagerly than the ones around 1-5 kHz
+    // Only the comparison is weighted, the kept bins go through untouched
+    if !perceptual {
+        return denoise_signal(samples, treshold_percentage);
+    }
+
+    let original_length = samples.len();
+    let (mut re, mut im) = fft_real_zero_padded(samples);
+    let n = re.len();
+
+    let weighted: Vec<f64> = re
+        .iter()
+        .zip(im.iter())
+        .enumerate()
+        .map(|(k, (re, im))| {
+            (re.powi(2) + im.powi(2)).sqrt() * a_weighting(bin_to_hz(k, n, sample_rate))
+        })
+        .collect();
+    let max_magnitude = weighted.iter().fold(0.0_f64, |a, &b| a.max(b));
+    let treshold = treshold_percentage * max_magnitude;
+
+    for k in 0..n {
+        if weighted[k] < treshold {
+            re[k] = 0.0;
+            im[k] = 0.0;
+        }
+    }
+
+    let (re_denoised, _) = ifft(&re, &im);
+    re_denoised[..original_length].to_vec()
+}
+
+pub fn low_pass_filter(samples: &[f64], cutoff_hz: f64, sample_rate: u32) -> Vec<f64> {
+    // Zeros every frequency above the cutoff
+    let original_length = samples.len();
+    let (mut re, mut im) = fft_real_zero_padded(samples);
+    let n = re.len();
+
+    for k in 0..n {
+        let frequency = bin_to_hz(k, n, sample_rate);
+        if frequency > cutoff_hz {
+            re[k] = 0.0;
+            im[k] = 0.0;
+        }
+    }
+
+    let (re_filtered, _) = ifft(&re, &im);
+    re_filtered[..original_length].to_vec()
+}
+
+pub fn notch_filter(
+    samples: &[f64],
+    fundamental_hz: f64,
+    num_harmonics: usize,
+    bandwidth_hz: f64,
+    sample_rate: u32,
+) -> Vec<f64> {
+    // Zeros a narrow band around the fundamental and each of its harmonics
+    // The fundamental itself counts as the first harmonic
+    let original_length = samples.len();
+    let (mut re, mut im) = fft_real_zero_padded(samples);
+    let n = re.len();
+
+    for k in 0..n {
+        // The mirrored bins map to the same frequency, which keeps the spectrum Hermitian
+        let frequency = bin_to_hz(k, n, sample_rate);
+        let is_hum = (1..=num_harmonics)
+            .any(|h| (frequency - h as f64 * fundamental_hz).abs() <= bandwidth_hz / 2.0);
+        if is_hum {
+            re[k] = 0.0;
+            im[k] = 0.0;
+        }
+    }
+
+    let (re_filtered, _) = ifft(&re, &im);
+    re_filtered[..original_length].to_vec()
+}
+
+// Denoises audio arriving in chunks (e.g. from a socket) without holding the whole signal
+// Streaming version of stft_denoise with frames of block_size - the same windowed frames
+// half a frame apart, denoised and overlap-added the same way, so the output is the same too
+// Frames are only denoised once complete, so the output lags behind the input by up to
+// block_size samples - finish() hands out the rest at the end of the stream
+#[derive(Debug, Clone)]
+pub struct OverlapSaveDenoiser {
+    frame_size: usize,
+    treshold_percentage: f64,
+    window: Vec<f64>,
+    gain: f64,
+    // Input not denoised yet, starts with the half frame of padding stft_frames adds
+    pending: Vec<f64>,
+    // Second half of the last denoised frame, the next frame's first half is added to it
+    carry: Vec<f64>,
+    // Output of the first frame only covers the padding and is dropped
+    started: bool,
+    received: usize,
+    emitted: usize,
+}
+
+impl OverlapSaveDenoiser {
+    pub fn new(block_size: usize, treshold_percentage: f64) -> OverlapSaveDenoiser {
+        // Rounded down to an even size, like stft_frames does
+        let frame_size = block_size.max(2) / 2 * 2;
+        let hop = frame_size / 2;
+        let window = hann_window(frame_size);
+        let gain = cola_factor(&window, hop);
+        OverlapSaveDenoiser {
+            frame_size,
+            treshold_percentage,
+            window,
+            gain,
+            pending: vec![0.0; hop],
+            carry: vec![0.0; hop],
+            started: false,
+            received: 0,
+            emitted: 0,
+        }
+    }
+
+    pub fn process_block(&mut self, input: &[f64]) -> Vec<f64> {
+        // Input of any length is accepted, the output is whatever got complete in the meantime
+        self.pending.extend_from_slice(input);
+        self.received += input.len();
+
+        let mut output = Vec::new();
+        while self.pending.len() >= self.frame_size {
+            output.extend(self.next_frame());
+        }
+        self.emitted += output.len();
+        output
+    }
+
+    pub fn finish(&mut self) -> Vec<f64> {
+        // The remaining output, the last frames padded with silence like in stft_frames
+        // Afterwards the denoiser starts over, ready for another stream
+        let remaining = self.received - self.emitted;
+        let mut output = Vec::new();
+        while output.len() < remaining {
+            self.pending
+                .resize(self.pending.len().max(self.frame_size), 0.0);
+            output.extend(self.next_frame());
+        }
+        output.truncate(remaining);
+
+        *self = OverlapSaveDenoiser::new(self.frame_size, self.treshold_percentage);
+        output
+    }
+
+    fn next_frame(&mut self) -> Vec<f64> {
+        // Denoises the frame at the start of pending and moves on by half a frame
+        // Returns the half frame of output no later frame overlaps anymore
+        let hop = self.frame_size / 2;
+        let frame: Vec<f64> = self.pending[..self.frame_size]
+            .iter()
+            .zip(self.window.iter())
+            .map(|(x, w)| x * w)
+            .collect();
+        let denoised = denoise_signal(&frame, self.treshold_percentage);
+        self.pending.drain(..hop);
+
+        let output: Vec<f64> = self
+            .carry
+            .iter()
+            .zip(denoised[..hop].iter())
+            .map(|(carry, x)| (carry + x) / self.gain)
+            .collect();
+        self.carry = denoised[hop..].to_vec();
+
+        if !self.started {
+            self.started = true;
+            return Vec::new();
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn white_noise(length: usize, seed: u64) -> Vec<f64> {
+        // Deterministic, uniform in -0.5..0.5
+        let mut state = seed;
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 11) as f64 / (1_u64 << 53) as f64 - 0.5
+            })
+            .collect()
+    }
+
+    fn bin_tone(bin: usize, n: usize, amplitude: f64) -> Vec<f64> {
+        // Exactly `bin` periods in n samples, so all of it lands in a single bin
+        (0..n)
+            .map(|i| amplitude * f64::sin(2. * PI * (bin * i) as f64 / n as f64))
+            .collect()
+    }
+
+    fn add(a: &[f64], b: &[f64]) -> Vec<f64> {
+        a.iter().zip(b.iter()).map(|(a, b)| a + b).collect()
+    }
+
+    fn error_power(actual: &[f64], expected: &[f64]) -> f64 {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .map(|(a, e)| (a - e).powi(2))
+            .sum::<f64>()
+            / actual.len() as f64
+    }
+
+    fn magnitudes(samples: &[f64]) -> Vec<f64> {
+        let (re, im) = fft_real_zero_padded(samples);
+        re.iter()
+            .zip(im.iter())
+            .map(|(re, im)| (re.powi(2) + im.powi(2)).sqrt())
+            .collect()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64], tolerance: f64) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!((a - e).abs() < tolerance, "sample {i}: {a} vs {e}");
+        }
+    }
+
+    #[test]
+    fn threshold_keeps_the_tone_and_drops_the_noise() {
+        let clean = bin_tone(100, 4096, 1000.0);
+        let noise: Vec<f64> = white_noise(4096, 1).iter().map(|x| x * 200.0).collect();
+        let noisy = add(&clean, &noise);
+
+        let denoised = denoise_signal(&noisy, 0.1);
+        assert_eq!(denoised.len(), noisy.len());
+        assert!(error_power(&denoised, &clean) < error_power(&noisy, &clean) / 100.0);
+
+        // Nothing is below a threshold of 0
+        assert_close(&denoise_signal(&noisy, 0.0), &noisy, 1e-6);
+        assert!(denoise_signal(&[], 0.1).is_empty());
+    }
+
+    #[test]
+    fn wiener_filter_improves_snr() {
+        let clean = bin_tone(100, 4096, 1000.0);
+        let noise: Vec<f64> = white_noise(4096, 4).iter().map(|x| x * 400.0).collect();
+        let noisy = add(&clean, &noise);
+        // Noise estimate from a separate, shorter fragment
+        let fragment: Vec<f64> = white_noise(1024, 5).iter().map(|x| x * 400.0).collect();
+
+        let filtered = wiener_filter(&noisy, &noise_psd(&fragment));
+        assert_eq!(filtered.len(), noisy.len());
+        assert!(error_power(&filtered, &clean) < error_power(&noisy, &clean) / 2.0);
+
+        assert_eq!(wiener_filter(&noisy, &[]), noisy);
+    }
+
+    #[test]
+    fn notch_filter_removes_the_hum() {
+        // Fundamental of 26 bins at 8 kHz (about 51 Hz) with two harmonics, under a 439 Hz tone
+        let tone = bin_tone(225, 4096, 1000.0);
+        let hum = add(
+            &add(&bin_tone(26, 4096, 500.0), &bin_tone(52, 4096, 300.0)),
+            &bin_tone(78, 4096, 200.0),
+        );
+        let fundamental = 26.0 * 8000.0 / 4096.0;
+
+        let filtered = notch_filter(&add(&tone, &hum), fundamental, 3, 1.0, 8000);
+        assert_close(&filtered, &tone, 1e-6);
+        let partly = notch_filter(&add(&tone, &hum), fundamental, 2, 1.0, 8000);
+        assert_close(&partly, &add(&tone, &bin_tone(78, 4096, 200.0)), 1e-6);
+
+        let low = low_pass_filter(&add(&tone, &hum), 300.0, 8000);
+        assert_close(&low, &hum, 1e-6);
+    }
+
+    fn noisy_tone(length: usize) -> Vec<f64> {
+        // A 440 Hz tone at 8 kHz under deterministic white noise
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        (0..length)
+            .map(|i| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                let noise = (state >> 11) as f64 / (1_u64 << 53) as f64 - 0.5;
+                1000.0 * f64::sin(2. * PI * 440.0 * i as f64 / 8000.0) + 300.0 * noise
+            })
+            .collect()
+    }
+
+    #[test]
+    fn streaming_matches_whole_signal_stft() {
+        let samples = noisy_tone(5000);
+        let expected = stft_denoise(&samples, 256, 30.0);
+
+        let mut denoiser = OverlapSaveDenoiser::new(256, 30.0);
+        // Twice, finish() has to leave it ready for the next stream
+        for _ in 0..2 {
+            let mut output = Vec::new();
+            let mut rest = samples.as_slice();
+            for size in [1, 7, 100, 256, 513, 1000].iter().cycle() {
+                if rest.is_empty() {
+                    break;
+                }
+                let (block, remaining) = rest.split_at((*size).min(rest.len()));
+                output.extend(denoiser.process_block(block));
+                rest = remaining;
+            }
+            output.extend(denoiser.finish());
+            assert_close(&output, &expected, 1e-9);
+        }
+    }
+
+    #[test]
+    fn streaming_short_and_empty_signals() {
+        let mut denoiser = OverlapSaveDenoiser::new(64, 30.0);
+        assert!(denoiser.finish().is_empty());
+
+        let samples = noisy_tone(10);
+        assert!(denoiser.process_block(&samples).is_empty());
+        assert_close(&denoiser.finish(), &stft_denoise(&samples, 64, 30.0), 1e-9);
+    }
+
+    #[test]
+    fn fft_lengths() {
+        let samples = noisy_tone(3000);
+        assert_eq!(denoise_signal(&samples, 0.1).len(), 3000);
+        assert_eq!(denoise_signal_padded(&samples, 0.1).len(), 4096);
+        let (exact, spectrum) = denoise_signal_with_spectrum(&samples, 0.0, FftLength::Exact);
+        assert_eq!(spectrum.magnitudes_before.len(), 3000);
+        assert_close(&exact, &samples, 1e-6);
+    }
+
+    #[test]
+    fn stft_with_zero_threshold_passes_through() {
+        let samples = noisy_tone(3001);
+        for frame_size in [2, 64, 255, 256] {
+            assert_close(&stft_denoise(&samples, frame_size, 0.0), &samples, 1e-6);
+        }
+        assert!(stft_denoise(&[], 256, 0.0).is_empty());
+        assert_eq!(cola_factor(&hann_window(256), 128), 1.0);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_stft_matches_serial() {
+        let samples = noisy_tone(10000);
+        assert_eq!(
+            par_stft(&samples, 512, 0.1),
+            stft_denoise(&samples, 512, 0.1)
+        );
+    }
+
+    #[test]
+    fn median_removes_a_spike() {
+        let mut samples: Vec<f64> = (0..20).map(|i| i as f64).collect();
+        samples[10] = 1000.0;
+
+        let median = median_filter(&samples, 3);
+        assert_eq!(median[10], 11.0);
+        assert_eq!(median[5], 5.0);
+        // The mean smears the spike over its neighbours instead
+        let mean = moving_average(&samples, 3);
+        assert!(mean[9] > 300.0 && mean[11] > 300.0);
+
+        assert_eq!(median_filter(&[1.0, 4.0], 3), vec![2.5, 2.5]);
+        assert_eq!(moving_average(&[1.0, 2.0, 3.0], 1), vec![1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn declick_bridges_clicks() {
+        let clean: Vec<f64> = (0..2000)
+            .map(|i| 1000.0 * f64::sin(2. * PI * 100.0 * i as f64 / 8000.0))
+            .collect();
+        let mut clicked = clean.clone();
+        clicked[500] += 8000.0;
+        clicked[1200] -= 6000.0;
+        clicked[1201] -= 6000.0;
+
+        let repaired = declick(&clicked, 0.1);
+        assert_close(&repaired, &clean, 20.0);
+        // The untouched samples stay exactly as they were
+        assert_eq!(repaired[..490], clean[..490]);
+
+        assert_eq!(declick(&clicked, 0.0), clicked);
+        assert_eq!(declick(&[1.0, 100.0], 0.1), vec![1.0, 100.0]);
+    }
+
+    #[test]
+    fn smooth_gate_leaves_fewer_stray_bins() {
+        let clean = bin_tone(100, 4096, 1000.0);
+        let noise: Vec<f64> = white_noise(4096, 6).iter().map(|x| x * 300.0).collect();
+        let noisy = add(&clean, &noise);
+        let treshold = 0.005 * magnitudes(&noisy).iter().fold(0.0_f64, |a, &b| a.max(b));
+
+        // Noise bins loud enough to be heard as separate tones
+        let stray = |samples: &[f64]| {
+            let magnitudes = magnitudes(samples);
+            (0..=2048_usize)
+                .filter(|&k| k.abs_diff(100) > 2 && magnitudes[k] >= treshold / 2.0)
+                .count()
+        };
+        let hard = stray(&denoise_signal(&noisy, 0.005));
+        let smooth = stray(&smooth_spectral_gate(&noisy, 0.005, 5));
+        assert!(hard > 0);
+        assert!(smooth < hard);
+
+        assert_eq!(smooth_spectral_gate(&noisy, 0.0, 5), noisy);
+        assert!(smooth_spectral_gate(&[], 0.1, 5).is_empty());
+    }
+
+    #[test]
+    fn cola_factor_of_overlapping_windows() {
+        // A quarter frame apart, four Hann windows overlap at every sample
+        assert!((cola_factor(&hann_window(256), 64) - 2.0).abs() < 1e-12);
+        assert_eq!(cola_factor(&[1.0; 4], 4), 1.0);
+        assert_eq!(cola_factor(&[1.0; 4], 0), 4.0);
+    }
+
+    #[test]
+    fn window_reduces_leakage() {
+        // 440 Hz falls between the bins, without a window it leaks into the whole spectrum
+        let clean: Vec<f64> = (0..4096)
+            .map(|i| 1000.0 * f64::sin(2. * PI * 440.0 * i as f64 / 8000.0))
+            .collect();
+        let noise: Vec<f64> = white_noise(4096, 2).iter().map(|x| x * 200.0).collect();
+        let noisy = add(&clean, &noise);
+
+        let plain = denoise_signal(&noisy, 0.05);
+        for window in [WindowKind::Hann, WindowKind::Hamming] {
+            let windowed = denoise_signal_windowed(&noisy, 0.05, window);
+            assert_eq!(windowed.len(), noisy.len());
+            assert!(error_power(&windowed, &clean) < error_power(&plain, &clean));
+        }
+
+        let hann = WindowKind::Hann.coefficients(5);
+        assert_close(&hann, &[0.0, 0.5, 1.0, 0.5, 0.0], 1e-12);
+        assert_eq!(WindowKind::Hamming.coefficients(1), vec![1.0]);
+    }
+
+    #[test]
+    fn sliding_minimum_matches_brute_force() {
+        let samples = white_noise(300, 3);
+        for (before, after) in [(0, 0), (1, 0), (0, 3), (5, 7), (40, 2), (500, 500)] {
+            let expected: Vec<f64> = (0..samples.len())
+                .map(|i| {
+                    samples[i.saturating_sub(before)..(i + after + 1).min(samples.len())]
+                        .iter()
+                        .fold(f64::INFINITY, |a, &b| a.min(b))
+                })
+                .collect();
+            assert_eq!(sliding_minimum(&samples, before, after), expected);
+        }
+        assert!(sliding_minimum(&[], 3, 3).is_empty());
+    }
+
+    #[test]
+    fn spectrogram_follows_a_sweep() {
+        // Linear chirp from 200 Hz to 3 kHz over a second at 8 kHz
+        let samples: Vec<f64> = (0..8000)
+            .map(|i| {
+                let t = i as f64 / 8000.0;
+                f64::sin(2. * PI * (200.0 * t + 1400.0 * t * t))
+            })
+            .collect();
+        let frames = spectrogram(&samples, 256, 128);
+        assert_eq!(frames.len(), (8000 - 256_usize).div_ceil(128) + 1);
+        assert!(frames.iter().all(|frame| frame.len() == 129));
+
+        let peaks: Vec<usize> = frames
+            .iter()
+            .map(|frame| {
+                (0..frame.len())
+                    .max_by(|&a, &b| frame[a].total_cmp(&frame[b]))
+                    .unwrap()
+            })
+            .collect();
+        assert!(peaks.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(peaks[0] < peaks[peaks.len() - 1]);
+
+        assert!(spectrogram(&[], 256, 128).is_empty());
+    }
+
+    #[test]
+    fn perceptual_threshold_prefers_audible_bins() {
+        // 48 kHz, bins 5, 85 and 1707 are about 59 Hz, 1 kHz and 20 kHz
+        let loud = bin_tone(85, 4096, 1000.0);
+        let quiet = add(&bin_tone(5, 4096, 100.0), &bin_tone(1707, 4096, 100.0));
+        let samples = add(&loud, &quiet);
+
+        assert_close(
+            &denoise_signal_weighted(&samples, 0.05, 48000, false),
+            &samples,
+            1e-6,
+        );
+        assert_close(
+            &denoise_signal_weighted(&samples, 0.05, 48000, true),
+            &loud,
+            1e-6,
+        );
+        assert!((a_weighting(1000.0) - 1.0).abs() < 1e-12);
+        assert!(a_weighting(20000.0) < a_weighting(4000.0));
+    }
+
+    #[test]
+    fn phase_mask_only_touches_bins_below_the_threshold() {
+        let noisy = add(
+            &bin_tone(100, 4096, 1000.0),
+            &white_noise(4096, 7)
+                .iter()
+                .map(|x| x * 300.0)
+                .collect::<Vec<f64>>(),
+        );
+        let masked = phase_mask(&noisy, 0.01, 42);
+        assert_eq!(masked, phase_mask(&noisy, 0.01, 42));
+        assert_ne!(masked, phase_mask(&noisy, 0.01, 43));
+        assert_ne!(masked, phase_mask(&noisy, 0.01, 0));
+
+        let before = magnitudes(&noisy);
+        let after = magnitudes(&masked);
+        let treshold = 0.01 * before.iter().fold(0.0_f64, |a, &b| a.max(b));
+        for (k, (b, a)) in before.iter().zip(after.iter()).enumerate() {
+            let expected = if *b >= treshold {
+                *b
+            } else {
+                PHASE_MASK_GAIN * b
+            };
+            assert!((a - expected).abs() < 1e-6, "bin {k}: {a} vs {expected}");
+        }
+        assert!(phase_mask(&[], 0.01, 42).is_empty());
+    }
+}
@@ -2,14 +2,38 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum WavError {
+    #[error("Not a WAV file - it starts with {0:?} instead of 'RIFF'")]
+    NotAWavFile(Vec<u8>),
     #[error("Invalid WAV header - expected 'RIFF' but found {0:?}")]
     InvalidRiffHeader(Vec<u8>),
     #[error("Invalid WAV format - expected 'WAVE' but found {0:?}")]
     InvalidWaveFormat(Vec<u8>),
     #[error("Invalid audio format - Pcm is the only one handled")]
     InvalidWAudioFormat,
+    #[error("Unsupported audio format (tag {0}) - compressed and float data cannot be processed")]
+    UnsupportedCompressedFormat(u16),
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
     #[error("Unexpected length of file")]
     UnexpectedLength,
+    #[error("Formats of the files do not match - {0}")]
+    FormatMismatch(String),
+    #[error("Invalid sample rate: {0}")]
+    InvalidSampleRate(u32),
+    #[error("Invalid time range - start has to be before the end and within the file")]
+    InvalidTimeRange,
+    #[error("Channels of different lengths: {0} vs {1} samples")]
+    ChannelLengthMismatch(usize, usize),
+    #[error("The file contains no audio data")]
+    EmptyAudioData,
+    #[error("Invalid channel index: {0}")]
+    InvalidChannel(usize),
+    #[error("Inconsistent fmt chunk - {0}")]
+    InconsistentFmt(String),
+    #[error("Invalid INFO tag id '{0}' - expected four ASCII characters")]
+    InvalidInfoTag(String),
+    #[error("Unsupported number of channels: {0} - only mono and stereo are handled")]
+    UnsupportedChannelCount(u16),
+    #[error("Invalid FFT size: {0} - it has to be a power of 2, at least 2")]
+    InvalidFftSize(usize),
 }
@@ -3,9 +3,14 @@ use std::f64::consts::PI;
 
 pub fn zero_pad(data: &[f64]) -> Vec<f64> {
     let n = data.len();
-    // Check if n is already a power of 2
+    // Nothing to pad, next_power_of_two() would invent a sample here (0 -> 1)
+    if n == 0 {
+        return Vec::new();
+    }
+
+    // Check if n is already a power of 2 (n == 1 included, 2^0)
     // Simple trick (x & (x-1) == 0)
-    if (n != 0) && (n & (n - 1) == 0) {
+    if n & (n - 1) == 0 {
         return data.to_vec();
     }
 
@@ -13,73 +18,130 @@ pub fn zero_pad(data: &[f64]) -> Vec<f64> {
     [data, &vec![0.0; x - n]].concat()
 }
 
+#[cfg(not(feature = "rustfft"))]
 pub fn fft(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    fft_native(re, im)
+}
+
+#[cfg(feature = "rustfft")]
+pub fn fft(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    // Same transform done by the rustfft crate, much faster for long signals
+    // It handles any length, but the callers keep padding to powers of 2 so the output is the same
+    rustfft_transform(re, im, rustfft::FftDirection::Forward)
+}
+
+#[cfg(feature = "rustfft")]
+fn rustfft_transform(
+    re: &[f64],
+    im: &[f64],
+    direction: rustfft::FftDirection,
+) -> (Vec<f64>, Vec<f64>) {
+    use rustfft::FftPlanner;
+    use rustfft::num_complex::Complex;
+    use std::cell::RefCell;
+
+    // Planning is the expensive part, the planner keeps every plan it made so each length
+    // (and direction) is only planned once per thread - STFT frames all share a single one
+    thread_local! {
+        static PLANNER: RefCell<FftPlanner<f64>> = RefCell::new(FftPlanner::new());
+    }
+
+    let mut buffer: Vec<Complex<f64>> = re
+        .iter()
+        .zip(im.iter())
+        .map(|(&re, &im)| Complex::new(re, im))
+        .collect();
+    let plan = PLANNER.with(|planner| planner.borrow_mut().plan_fft(buffer.len(), direction));
+    plan.process(&mut buffer);
+    buffer.iter().map(|c| (c.re, c.im)).unzip()
+}
+
+pub fn fft_native(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
     // https://en.wikipedia.org/wiki/Cooley%E2%80%93Tukey_FFT_algorithm
 
     // In order to use fft, the length of input HAS TO BE POWER OF 2
     // Otherwise the algorithm will not work
     // Working with audio it should not be a problem, we may truncate output afterwards
 
+    // The whole recursion works in place on a single pair of buffers,
+    // with one scratch pair reused for splitting at every level
     let n = re.len();
+    let mut re_out = re.to_vec();
+    let mut im_out = im.to_vec();
+    let mut re_scratch = vec![0.; n];
+    let mut im_scratch = vec![0.; n];
 
-    if (n <= 1) {
-        return (re.to_vec(), im.to_vec());
-    }
+    fft_rec(&mut re_out, &mut im_out, &mut re_scratch, &mut im_scratch);
 
-    // Even k's
-    let mut re_Ek = Vec::with_capacity(n / 2);
-    let mut im_Ek = Vec::with_capacity(n / 2);
+    (re_out, im_out)
+}
 
-    // Odd k's
-    let mut re_Ok = Vec::with_capacity(n / 2);
-    let mut im_Ok = Vec::with_capacity(n / 2);
+fn fft_rec(re: &mut [f64], im: &mut [f64], re_scratch: &mut [f64], im_scratch: &mut [f64]) {
+    let n = re.len();
 
-    // Functional hell but works
-    for (i, (&re_val, &im_val)) in re.iter().zip(im.iter()).enumerate() {
-        if i % 2 == 0 {
-            re_Ek.push(re_val);
-            im_Ek.push(im_val);
-        } else {
-            re_Ok.push(re_val);
-            im_Ok.push(im_val);
-        }
+    if n <= 1 {
+        return;
     }
 
-    // Perform FFT on Ek's and Ok's
-    let (re_Ek_fft, im_Ek_fft) = fft(&re_Ek, &im_Ek);
-    let (re_Ok_fft, im_Ok_fft) = fft(&re_Ok, &im_Ok);
+    // Even k's go to the first half, odd k's to the second one
+    for i in 0..n / 2 {
+        re_scratch[i] = re[2 * i];
+        im_scratch[i] = im[2 * i];
+        re_scratch[i + n / 2] = re[2 * i + 1];
+        im_scratch[i + n / 2] = im[2 * i + 1];
+    }
+    re.copy_from_slice(re_scratch);
+    im.copy_from_slice(im_scratch);
 
+    // Perform FFT on Ek's and Ok's, each half gets its own half of the scratch buffers
+    {
+        let (re_even, re_odd) = re.split_at_mut(n / 2);
+        let (im_even, im_odd) = im.split_at_mut(n / 2);
+        let (re_scratch_even, re_scratch_odd) = re_scratch.split_at_mut(n / 2);
+        let (im_scratch_even, im_scratch_odd) = im_scratch.split_at_mut(n / 2);
+        fft_rec(re_even, im_even, re_scratch_even, im_scratch_even);
+        fft_rec(re_odd, im_odd, re_scratch_odd, im_scratch_odd);
+    }
 
     // Here goes the pseudo-code part from wikipedia,
     // visual explanation: https://en.wikipedia.org/wiki/Cooley%E2%80%93Tukey_FFT_algorithm#/media/File:DIT-FFT-butterfly.svg
 
-    // Prepare output vectors
-    let mut re_out = [re_Ek_fft, re_Ok_fft].concat();
-    let mut im_out = [im_Ek_fft, im_Ok_fft].concat();
-
-    
     for k in 0..n / 2 {
-        let re_p = re_out[k];
-        let im_p = im_out[k];
+        let re_p = re[k];
+        let im_p = im[k];
 
         // e^(-2*PI*k/n) = cos(2 * PI * k / n) - isin(2 * PI * k /n)
         // [ cos(2 * PI * k / n) - isin(2 * PI * k /n) ] * (x + yi) ==
         // == xcos() + ysin() + i[ ycos() - xsin() ]
         let angle = 2. * PI * k as f64 / n as f64;
-        let re_q = re_out[k + n / 2] * f64::cos(angle) + im_out[k + n / 2] * f64::sin(angle);
-        let im_q = -re_out[k + n / 2] * f64::sin(angle) + im_out[k + n / 2] * f64::cos(angle);
+        let re_q = re[k + n / 2] * f64::cos(angle) + im[k + n / 2] * f64::sin(angle);
+        let im_q = -re[k + n / 2] * f64::sin(angle) + im[k + n / 2] * f64::cos(angle);
 
-        re_out[k] = re_p + re_q;
-        re_out[k + n/2] = re_p - re_q;
+        re[k] = re_p + re_q;
+        re[k + n / 2] = re_p - re_q;
 
-        im_out[k] = im_p + im_q;
-        im_out[k + n/2] = im_p - im_q;
+        im[k] = im_p + im_q;
+        im[k + n / 2] = im_p - im_q;
     }
+}
 
-    (re_out, im_out)
+#[cfg(not(feature = "rustfft"))]
+pub fn ifft(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    ifft_native(re, im)
 }
 
+#[cfg(feature = "rustfft")]
 pub fn ifft(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    // rustfft leaves the inverse unnormalized as well, the 1/n is applied here
+    let n = re.len() as f64;
+    let (re_out, im_out) = rustfft_transform(re, im, rustfft::FftDirection::Inverse);
+    (
+        re_out.iter().map(|&x| x / n).collect(),
+        im_out.iter().map(|&x| x / n).collect(),
+    )
+}
+
+pub fn ifft_native(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
   // https://dsp.stackexchange.com/questions/36082/calculate-ifft-using-only-fft
   // [...] So the recipe is:
   //  - Complex conjugate the given sequence that we want to inverse DFT
@@ -91,7 +153,7 @@ pub fn ifft(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
 
   let im_conj: Vec<f64> = im.iter().map(|&x| -x).collect();
 
-  let (re_fft, im_fft) = fft(&re, &im_conj);
+  let (re_fft, im_fft) = fft_native(re, &im_conj);
 
   let re_out =   re_fft.iter().map(|&x| x / n as f64).collect();
   let im_out: Vec<f64> = im_fft.iter().map(|&x| -x / n as f64).collect(); 
@@ -104,19 +166,320 @@ pub fn ifft(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
 pub fn fft_real(re: &[f64]) -> (Vec<f64>, Vec<f64>) {
   let n = re.len();
   let im: Vec<f64> = vec![0.; n];
-  fft(&re, &im)
+  fft(re, &im)
+}
+
+pub fn prepare_fft_buffers(length: usize) -> (Vec<f64>, Vec<f64>) {
+  // Zeroed real and imaginary parts for a signal of the given length, padded to a power of 2
+  // (same length zero_pad gives), the signal only has to be copied over the start
+  // Channels of a file all have the same length, so they all end up with the same buffers
+  let n = if length == 0 { 0 } else { length.next_power_of_two() };
+  (vec![0.; n], vec![0.; n])
 }
 
 pub fn fft_zero_padded(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
-  let re_pad = zero_pad(&re);
-  let im_pad = zero_pad(&im);
+  let (mut re_pad, mut im_pad) = prepare_fft_buffers(re.len());
+  re_pad[..re.len()].copy_from_slice(re);
+  im_pad[..im.len()].copy_from_slice(im);
   fft(&re_pad, &im_pad)
 }
 
 pub fn fft_real_zero_padded(re: &[f64]) -> (Vec<f64>, Vec<f64>) {
-  let re_pad = zero_pad(&re);
-
-  let n = re_pad.len();
-  let im_pad: Vec<f64> = vec![0.; n];
+  let (mut re_pad, im_pad) = prepare_fft_buffers(re.len());
+  re_pad[..re.len()].copy_from_slice(re);
   fft(&re_pad, &im_pad)
-}
\ No newline at end of file
+}
+
+pub fn bin_to_hz(bin: usize, n: usize, sample_rate: u32) -> f64 {
+    // Bins above n/2 are the mirrored negative frequencies, so they map back below Nyquist
+    // An empty spectrum has no bins, 0 Hz is returned instead of dividing by 0
+    if n == 0 {
+        return 0.0;
+    }
+    let bin = bin % n;
+    bin.min(n - bin) as f64 * sample_rate as f64 / n as f64
+}
+
+pub fn hz_to_bin(hz: f64, n: usize, sample_rate: u32) -> usize {
+    // Nearest bin of the positive half, frequencies above Nyquist land on the Nyquist bin
+    // Bin 0 for an empty spectrum, same as bin_to_hz
+    if n == 0 || sample_rate == 0 {
+        return 0;
+    }
+    let bin = (hz.abs() * n as f64 / sample_rate as f64).round() as usize;
+    bin.min(n / 2)
+}
+
+const SPECTRUM_FLOOR_DB: f64 = -120.0;
+
+pub fn magnitude_spectrum_db(samples: &[f64]) -> Vec<f64> {
+    // Magnitudes in decibels (20 * log10), from DC up to and including Nyquist
+    // The other half mirrors this one for real signals
+    // Silent bins would give -inf, so everything is floored at SPECTRUM_FLOOR_DB
+    let (re, im) = fft_real_zero_padded(samples);
+    let n = re.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    re.iter()
+        .zip(im.iter())
+        .take(n / 2 + 1)
+        .map(|(re, im)| {
+            let magnitude = (re.powi(2) + im.powi(2)).sqrt();
+            (20.0 * magnitude.log10()).max(SPECTRUM_FLOOR_DB)
+        })
+        .collect()
+}
+
+fn complex_mul((re_a, im_a): (f64, f64), (re_b, im_b): (f64, f64)) -> (f64, f64) {
+    (re_a * re_b - im_a * im_b, re_a * im_b + im_a * re_b)
+}
+
+pub fn fft_bluestein(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    // https://en.wikipedia.org/wiki/Chirp_Z-transform#Bluestein's_algorithm
+
+    // Computes the DFT of arbitrary length by rewriting it as a convolution,
+    // which in turn can be calculated with power of 2 FFTs (and no padding of the signal itself)
+
+    let n = re.len();
+
+    // Nothing to gain for powers of 2
+    if n == 0 || n & (n - 1) == 0 {
+        return fft(re, im);
+    }
+
+    // Chirp: w_k = e^(-i * PI * k^2 / n)
+    // The chirp is periodic in k^2 with period 2n, taking the modulo keeps the angle precise
+    let chirp: Vec<(f64, f64)> = (0..n)
+        .map(|k| {
+            let angle = PI * ((k * k) % (2 * n)) as f64 / n as f64;
+            (f64::cos(angle), -f64::sin(angle))
+        })
+        .collect();
+
+    // The convolution has to fit 2n - 1 elements without wrapping around
+    let m = (2 * n - 1).next_power_of_two();
+
+    // a_k = x_k * w_k, zero padded to m
+    let mut re_a = vec![0.0; m];
+    let mut im_a = vec![0.0; m];
+    for (k, &w) in chirp.iter().enumerate() {
+        (re_a[k], im_a[k]) = complex_mul((re[k], im[k]), w);
+    }
+
+    // b_k = conj(w_k), mirrored at the end so that b_(m-k) = b_k
+    let mut re_b = vec![0.0; m];
+    let mut im_b = vec![0.0; m];
+    for (k, &(re_w, im_w)) in chirp.iter().enumerate() {
+        re_b[k] = re_w;
+        im_b[k] = -im_w;
+        if k > 0 {
+            re_b[m - k] = re_w;
+            im_b[m - k] = -im_w;
+        }
+    }
+
+    // Convolution theorem: a * b = IFFT(FFT(a) . FFT(b))
+    let (re_a_fft, im_a_fft) = fft(&re_a, &im_a);
+    let (re_b_fft, im_b_fft) = fft(&re_b, &im_b);
+    let (re_prod, im_prod): (Vec<f64>, Vec<f64>) = (0..m)
+        .map(|k| complex_mul((re_a_fft[k], im_a_fft[k]), (re_b_fft[k], im_b_fft[k])))
+        .unzip();
+    let (re_conv, im_conv) = ifft(&re_prod, &im_prod);
+
+    // X_k = w_k * (a * b)_k
+    chirp
+        .iter()
+        .enumerate()
+        .map(|(k, &w)| complex_mul((re_conv[k], im_conv[k]), w))
+        .unzip()
+}
+
+pub fn ifft_bluestein(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    // Same conjugation trick as in ifft
+    let n = re.len();
+
+    let im_conj: Vec<f64> = im.iter().map(|&x| -x).collect();
+
+    let (re_fft, im_fft) = fft_bluestein(re, &im_conj);
+
+    let re_out = re_fft.iter().map(|&x| x / n as f64).collect();
+    let im_out = im_fft.iter().map(|&x| -x / n as f64).collect();
+
+    (re_out, im_out)
+}
+
+#[cfg(test)]
+pub(crate) fn dft_naive(re: &[f64], im: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    // Straight from the definition, O(n^2)
+    // X_k = sum over t of x_t * e^(-2 * PI * i * k * t / n)
+    // Far too slow for audio, only meant as a ground truth to validate the FFTs against
+
+    let n = re.len();
+
+    (0..n)
+        .map(|k| {
+            (0..n).fold((0.0, 0.0), |(re_acc, im_acc), t| {
+                // k * t taken modulo n, so the angle stays small and precise
+                let angle = -2. * PI * ((k * t) % n) as f64 / n as f64;
+                let (re_term, im_term) =
+                    complex_mul((re[t], im[t]), (f64::cos(angle), f64::sin(angle)));
+                (re_acc + re_term, im_acc + im_term)
+            })
+        })
+        .unzip()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_signal(n: usize) -> (Vec<f64>, Vec<f64>) {
+        // Arbitrary complex values without any structure the FFTs could get lucky on
+        let re = (0..n)
+            .map(|t| f64::sin(1.3 * t as f64 + 0.2) + 0.5 * f64::cos(0.37 * (t * t) as f64))
+            .collect();
+        let im = (0..n)
+            .map(|t| f64::cos(2.9 * t as f64) - 0.25 * f64::sin(0.11 * t as f64))
+            .collect();
+        (re, im)
+    }
+
+    fn assert_close(
+        actual: &(Vec<f64>, Vec<f64>),
+        expected: &(Vec<f64>, Vec<f64>),
+        tolerance: f64,
+    ) {
+        assert_eq!(actual.0.len(), expected.0.len());
+        assert_eq!(actual.1.len(), expected.1.len());
+        let parts = [(&actual.0, &expected.0), (&actual.1, &expected.1)];
+        for (actual, expected) in parts {
+            for (k, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+                assert!((a - e).abs() < tolerance, "bin {k}: {a} vs {e}");
+            }
+        }
+    }
+
+    #[test]
+    fn bluestein_finds_a_single_frequency() {
+        // e^(2 * PI * i * 3 * t / n) puts all of its energy (n) into bin 3
+        for n in [5, 12, 100] {
+            let (re, im): (Vec<f64>, Vec<f64>) = (0..n)
+                .map(|t| {
+                    let angle = 2. * PI * ((3 * t) % n) as f64 / n as f64;
+                    (f64::cos(angle), f64::sin(angle))
+                })
+                .unzip();
+            let expected = (0..n)
+                .map(|k| if k == 3 { n as f64 } else { 0.0 })
+                .collect();
+            assert_close(&fft_bluestein(&re, &im), &(expected, vec![0.0; n]), 1e-8);
+        }
+    }
+
+    #[test]
+    fn inverse_transforms_give_the_signal_back() {
+        let (re, im) = test_signal(64);
+        let (re_fft, im_fft) = fft(&re, &im);
+        assert_close(&ifft(&re_fft, &im_fft), &(re.clone(), im.clone()), 1e-9);
+
+        for n in [3, 100] {
+            let (re, im) = test_signal(n);
+            let (re_fft, im_fft) = fft_bluestein(&re, &im);
+            assert_close(&ifft_bluestein(&re_fft, &im_fft), &(re, im), 1e-9);
+        }
+    }
+
+    #[test]
+    fn fft_matches_naive_dft() {
+        for n in [1, 2, 4, 16] {
+            let (re, im) = test_signal(n);
+            assert_close(&fft(&re, &im), &dft_naive(&re, &im), 1e-9);
+        }
+    }
+
+    #[test]
+    fn native_fft_matches_naive_dft() {
+        // The recursion working in place on the scratch buffers, checked on larger sizes too
+        for n in [1, 2, 8, 64, 256] {
+            let (re, im) = test_signal(n);
+            assert_close(&fft_native(&re, &im), &dft_naive(&re, &im), 1e-9);
+        }
+    }
+
+    #[cfg(feature = "rustfft")]
+    #[test]
+    fn rustfft_backend_matches_native_fft() {
+        // Twice over, the second time with the plans already cached
+        for n in [1, 2, 16, 1024, 16, 1024] {
+            let (re, im) = test_signal(n);
+            assert_close(&fft(&re, &im), &fft_native(&re, &im), 1e-6);
+            assert_close(&ifft(&re, &im), &ifft_native(&re, &im), 1e-6);
+        }
+    }
+
+    #[test]
+    fn bins_map_to_frequencies_and_back() {
+        assert_eq!(bin_to_hz(0, 1024, 48000), 0.0);
+        assert_eq!(bin_to_hz(512, 1024, 48000), 24000.0);
+        // Mirrored negative frequency
+        assert_eq!(
+            bin_to_hz(1024 - 10, 1024, 48000),
+            bin_to_hz(10, 1024, 48000)
+        );
+        assert_eq!(hz_to_bin(bin_to_hz(10, 1024, 48000), 1024, 48000), 10);
+        // Above Nyquist
+        assert_eq!(hz_to_bin(30000.0, 1024, 48000), 512);
+    }
+
+    #[test]
+    fn empty_spectrum_has_no_frequencies() {
+        assert_eq!(bin_to_hz(0, 0, 48000), 0.0);
+        assert_eq!(bin_to_hz(5, 0, 48000), 0.0);
+        assert_eq!(hz_to_bin(1000.0, 0, 48000), 0);
+        assert_eq!(hz_to_bin(1000.0, 1024, 0), 0);
+    }
+
+    #[test]
+    fn zero_pad_lengths() {
+        assert_eq!(zero_pad(&[]).len(), 0);
+        assert_eq!(zero_pad(&[0.5]), vec![0.5]);
+        assert_eq!(zero_pad(&[1.0, 2.0, 3.0]), vec![1.0, 2.0, 3.0, 0.0]);
+        assert_eq!(zero_pad(&[1.0; 8]).len(), 8);
+    }
+
+    #[test]
+    fn zero_padded_fft_pads_to_a_power_of_two() {
+        let (re, im) = test_signal(5);
+        let padded = (zero_pad(&re), zero_pad(&im));
+        assert_close(
+            &fft_zero_padded(&re, &im),
+            &fft(&padded.0, &padded.1),
+            1e-12,
+        );
+        assert_close(&fft_real_zero_padded(&re), &fft_real(&padded.0), 1e-12);
+        assert_eq!(prepare_fft_buffers(0).0.len(), 0);
+        assert_eq!(prepare_fft_buffers(1000).1.len(), 1024);
+    }
+
+    #[test]
+    fn magnitude_spectrum_peaks_at_the_tone() {
+        // 8 whole periods in 64 samples put all the energy into bin 8
+        let samples: Vec<f64> = (0..64)
+            .map(|t| f64::sin(2. * PI * 8.0 * t as f64 / 64.0))
+            .collect();
+        let spectrum = magnitude_spectrum_db(&samples);
+        assert_eq!(spectrum.len(), 33);
+
+        let peak = spectrum[8];
+        assert!(peak > 20.0);
+        for (k, &db) in spectrum.iter().enumerate() {
+            if k != 8 {
+                assert!(peak - db > 100.0, "bin {k}: {db} dB");
+                assert!(db >= SPECTRUM_FLOOR_DB);
+            }
+        }
+        assert!(magnitude_spectrum_db(&[]).is_empty());
+    }
+}
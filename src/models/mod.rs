@@ -2,5 +2,9 @@ pub mod wav_file;
 pub mod audio_samples;
 pub mod errors;
 pub mod fft;
+pub mod denoise;
+pub mod biquad;
+pub mod config;
 pub mod wav_source;
-pub(crate) mod tui_app;
\ No newline at end of file
+pub mod wav_writer;
+pub mod tui_app;
\ No newline at end of file
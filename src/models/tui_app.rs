@@ -1,107 +1,336 @@
+use crate::models::config::{CONFIG_FILE, Config};
 use crate::models::wav_file::WavFile;
-use crate::models::wav_source::WavSource;
+use crate::models::wav_source::{PlaybackPosition, TrackedSource, WavSource};
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Constraint, Layout, Rect};
 use ratatui::prelude::{Line, StatefulWidget, Stylize, Widget};
 use ratatui::style::{Color, Style};
 use ratatui::symbols::border;
 use ratatui::text::Span;
-use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, ListState};
+use ratatui::widgets::{Block, Borders, Clear, Gauge, List, ListItem, ListState, Paragraph, Wrap};
 use ratatui::{DefaultTerminal, Frame};
 use rodio::Source;
-use std::path::PathBuf;
-use std::sync::mpsc;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
-use std::time::{Duration, Instant};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
 use std::{env, fs, io, thread};
 
-pub(crate) enum Event {
+pub enum Event {
     Input(crossterm::event::KeyEvent),
     // FileSelected(WavFile),
     SoundProgress(f64),
-    SinksReady(rodio::Sink, rodio::Sink, Instant, Duration),
+    SinksReady(
+        rodio::Sink,
+        rodio::Sink,
+        rodio::Sink,
+        PlaybackPosition,
+        Duration,
+    ),
+    FileLoaded(String, Arc<WavFile>, f64),
     ProgressLabel(String, bool),
+    Error(String),
+}
+
+// Which track is heard, the toggle key goes through them in this order
+// Difference is the original minus the denoised one, i.e. what the denoising removed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlaybackMode {
+    Original,
+    Denoised,
+    Difference,
+}
+
+impl PlaybackMode {
+    fn next(self) -> PlaybackMode {
+        match self {
+            PlaybackMode::Original => PlaybackMode::Denoised,
+            PlaybackMode::Denoised => PlaybackMode::Difference,
+            PlaybackMode::Difference => PlaybackMode::Original,
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            PlaybackMode::Original => Color::Green,
+            PlaybackMode::Denoised => Color::Red,
+            PlaybackMode::Difference => Color::Magenta,
+        }
+    }
 }
 
 pub struct App {
     files: Option<Vec<String>>,
     path: Option<PathBuf>,
+    output_path: Option<PathBuf>,
+    config: Config,
     selected: usize,
     exit: bool,
     progress_bar_color: Color,
     sound_progress: f64,
     threshold: f64,
+    thresholds: HashMap<String, f64>,
     tx: Sender<Event>,
-    sink_original: Option<rodio::Sink>,
-    sink_denoised: Option<rodio::Sink>,
-    start_time: Option<Instant>,
+    sink_original: Option<Arc<rodio::Sink>>,
+    sink_denoised: Option<Arc<rodio::Sink>>,
+    sink_difference: Option<Arc<rodio::Sink>>,
+    playing_file: Option<String>,
+    loaded_wav: Option<(String, Arc<WavFile>)>,
+    clipping_ratio: Option<f64>,
+    playback_mode: PlaybackMode,
+    crossfade_generation: Arc<AtomicUsize>,
+    playback_position: Option<PlaybackPosition>,
     duration: Option<Duration>,
     ready_to_play: bool,
     label: String,
+    error: Option<String>,
+}
+
+fn load_wav(
+    playback_tx: &Sender<Event>,
+    path: PathBuf,
+    filename: &String,
+    cached_wav: Option<Arc<WavFile>>,
+) -> io::Result<Arc<WavFile>> {
+    // The same file played (or saved) again is not read from disk a second time
+    Ok(match cached_wav {
+        Some(wav) => wav,
+        None => {
+            let full_path = path.join(filename);
+            let file_path = full_path
+                .to_str()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid file path"))?;
+            let wav = Arc::new(
+                WavFile::from_wav_file(file_path)
+                    .map_err(|e| io::Error::other(format!("Error loading WAV: {:?}", e)))?,
+            );
+            // Clipped peaks cannot be recovered, the user is warned about them
+            let clipping_ratio = wav
+                .clipping_ratio()
+                .map_err(|e| io::Error::other(format!("Error reading samples: {:?}", e)))?;
+            playback_tx
+                .send(Event::FileLoaded(
+                    filename.clone(),
+                    wav.clone(),
+                    clipping_ratio,
+                ))
+                .map_err(io::Error::other)?;
+            wav
+        }
+    })
 }
 
 fn play_file(
     playback_tx: Sender<Event>,
     path: PathBuf,
+    output_path: PathBuf,
     filename: &String,
     threshold: f64,
+    cached_wav: Option<Arc<WavFile>>,
+    open_output: impl FnOnce() -> Option<AudioOutput>,
 ) -> io::Result<()> {
-    let (_stream, stream_handle) =
-        rodio::OutputStream::try_default().map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-    let sink1 = rodio::Sink::try_new(&stream_handle)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-    let sink2 = rodio::Sink::try_new(&stream_handle)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-    let full_path = path.join(filename);
-    let file_path = full_path
-        .to_str()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid file path"))?;
+    let wav = load_wav(&playback_tx, path, filename, cached_wav)?;
+    let denoised_wav = denoise_and_save(&wav, &output_path, filename, threshold)?;
 
-    let save_path = path
-        .join("denoised")
-        .join(filename)
-        .to_str()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid save path"))?
-        .to_string();
+    // Without an audio device (CI, headless servers) denoising and saving is all that can be done
+    let Some((_stream, sink1, sink2, sink3)) = open_output() else {
+        playback_tx
+            .send(Event::ProgressLabel(
+                String::from(NO_AUDIO_DEVICE_LABEL),
+                true,
+            ))
+            .map_err(io::Error::other)?;
+        return Ok(());
+    };
 
-    let wav = WavFile::from_wav_file(file_path)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Error loading WAV: {:?}", e)))?;
+    let difference_wav = difference(&wav, &denoised_wav)?;
 
-    let mut denoised_wav = wav.clone();
-    denoised_wav
-        .denoise_data_fft(threshold)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Denoise failed: {:?}", e)))?;
-    denoised_wav
-        .save_to_file(&save_path)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Save failed: {:?}", e)))?;
-
-    let source = WavSource::from_wav_file(&wav);
-    let denoised_source = WavSource::from_wav_file(&denoised_wav);
+    let source = WavSource::from_wav_file_ref(&wav);
+    let denoised_source = WavSource::from_wav_file(denoised_wav);
+    let difference_source = WavSource::from_wav_file(difference_wav);
 
     let total_duration = source
         .total_duration()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Failed to get total duration"))?;
+        .ok_or_else(|| io::Error::other("Failed to get total duration"))?;
 
+    // The original track is the one followed by the progress bar, both play in sync
+    let (source, position) = TrackedSource::new(source);
     sink1.append(source);
     sink2.append(denoised_source);
+    sink3.append(difference_source);
     sink1.set_volume(1.0);
     sink2.set_volume(0.0);
+    sink3.set_volume(0.0);
 
     playback_tx
         .send(Event::SinksReady(
             sink1,
             sink2,
-            Instant::now(),
+            sink3,
+            position.clone(),
             total_duration,
         ))
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        .map_err(io::Error::other)?;
 
-    thread::sleep(Duration::from_secs(total_duration.as_secs()));
+    // The stream stops playing once dropped, so it is kept until the track ends
+    // (or the sinks are dropped for the next one)
+    position.wait_until_finished();
 
     Ok(())
 }
 
+const NO_AUDIO_DEVICE_LABEL: &str = "No audio device; saved denoised file";
+
+type AudioOutput = (rodio::OutputStream, rodio::Sink, rodio::Sink, rodio::Sink);
+
+fn open_output() -> Option<AudioOutput> {
+    // The stream has to be kept alive for as long as the sinks are playing
+    let (stream, stream_handle) = rodio::OutputStream::try_default().ok()?;
+    let sink1 = rodio::Sink::try_new(&stream_handle).ok()?;
+    let sink2 = rodio::Sink::try_new(&stream_handle).ok()?;
+    let sink3 = rodio::Sink::try_new(&stream_handle).ok()?;
+    Some((stream, sink1, sink2, sink3))
+}
+
+fn save_file(
+    save_tx: Sender<Event>,
+    path: PathBuf,
+    output_path: PathBuf,
+    filename: &String,
+    threshold: f64,
+    cached_wav: Option<Arc<WavFile>>,
+    saved_label: String,
+) -> io::Result<()> {
+    // Same as play_file up to the saving, without any playback
+    let wav = load_wav(&save_tx, path, filename, cached_wav)?;
+    denoise_and_save(&wav, &output_path, filename, threshold)?;
+    save_tx
+        .send(Event::ProgressLabel(saved_label, true))
+        .map_err(io::Error::other)
+}
+
+fn difference(wav: &WavFile, denoised_wav: &WavFile) -> io::Result<WavFile> {
+    wav.difference(denoised_wav)
+        .map_err(|e| io::Error::other(format!("Difference failed: {:?}", e)))
+}
+
+fn denoise_and_save(
+    wav: &WavFile,
+    output_path: &Path,
+    filename: &String,
+    threshold: f64,
+) -> io::Result<WavFile> {
+    // Files from subdirectories are saved into the same subtree of the output directory
+    let full_save_path = output_path.join(filename);
+    if let Some(parent) = full_save_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let save_path = full_save_path
+        .to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid save path"))?
+        .to_string();
+
+    let mut denoised_wav = wav.clone();
+    denoised_wav
+        .denoise_data_fft(threshold, None)
+        .map_err(|e| io::Error::other(format!("Denoise failed: {:?}", e)))?;
+    denoised_wav
+        .save_to_file(&save_path)
+        .map_err(|e| io::Error::other(format!("Save failed: {:?}", e)))?;
+    Ok(denoised_wav)
+}
+
+fn redenoise(
+    wav: Arc<WavFile>,
+    output_path: PathBuf,
+    filename: &String,
+    threshold: f64,
+    sink_original: Arc<rodio::Sink>,
+    sink_denoised: Arc<rodio::Sink>,
+    sink_difference: Arc<rodio::Sink>,
+) -> io::Result<()> {
+    let denoised_wav = denoise_and_save(&wav, &output_path, filename, threshold)?;
+    let difference_wav = difference(&wav, &denoised_wav)?;
+
+    // The new tracks pick up where the original one is, so all of them stay in sync
+    let position = sink_original.get_pos();
+    for (sink, new_wav) in [
+        (sink_denoised, denoised_wav),
+        (sink_difference, difference_wav),
+    ] {
+        let source = WavSource::from_wav_file(new_wav).skip_duration(position);
+        sink.clear();
+        sink.append(source);
+        sink.play();
+    }
+    Ok(())
+}
+
+const CROSSFADE_DURATION: Duration = Duration::from_millis(50);
+const CROSSFADE_STEPS: u32 = 10;
+
+fn crossfade_steps(from: f32, to: f32, steps: u32) -> Vec<f32> {
+    // Volumes to go through, the last one being the target
+    (1..=steps)
+        .map(|i| from + (to - from) * i as f32 / steps as f32)
+        .collect()
+}
+
+fn crossfade(
+    sinks: [Arc<rodio::Sink>; 3],
+    mode: PlaybackMode,
+    generation: Arc<AtomicUsize>,
+    id: usize,
+) {
+    // Ramps the volumes instead of switching them instantly, which would click
+    // Starts from the current volumes, so interrupting a fade in progress is smooth as well
+    // The sinks are in the order of PlaybackMode, the one of the mode goes up, the rest down
+    let ramps: Vec<Vec<f32>> = sinks
+        .iter()
+        .enumerate()
+        .map(|(i, sink)| {
+            let target = if i == mode as usize { 1.0 } else { 0.0 };
+            crossfade_steps(sink.volume(), target, CROSSFADE_STEPS)
+        })
+        .collect();
+
+    for step in 0..CROSSFADE_STEPS as usize {
+        // Another toggle happened in the meantime, let the newer fade take over
+        if generation.load(Ordering::SeqCst) != id {
+            return;
+        }
+        for (sink, ramp) in sinks.iter().zip(ramps.iter()) {
+            sink.set_volume(ramp[step]);
+        }
+        thread::sleep(CROSSFADE_DURATION / CROSSFADE_STEPS);
+    }
+}
+
+const THRESHOLDS_FILE: &str = "thresholds.json";
+
+fn load_thresholds(data_path: &Path) -> HashMap<String, f64> {
+    // Missing or broken file simply means nothing is remembered yet
+    fs::read_to_string(data_path.join(THRESHOLDS_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_thresholds(data_path: &Path, thresholds: &HashMap<String, f64>) -> io::Result<()> {
+    let content = serde_json::to_string_pretty(thresholds).map_err(io::Error::other)?;
+    fs::write(data_path.join(THRESHOLDS_FILE), content)
+}
+
+fn key_label(key: char) -> String {
+    format!("<{}>", key.to_ascii_uppercase())
+}
+
+fn play_label(config: &Config) -> String {
+    format!("Press {} to play the sound", key_label(config.keys.play))
+}
+
 fn format_time(current: u64, total: u64) -> String {
     let format = |t: u64| {
         let minutes = t / 60;
@@ -113,34 +342,70 @@ fn format_time(current: u64, total: u64) -> String {
 
 fn load_progress_bar(
     progress_tx: Sender<Event>,
-    start_time: Instant,
+    position: PlaybackPosition,
     total_duration: Duration,
+    idle_label: String,
 ) -> io::Result<()> {
     let mut progress = 0.0;
-    while progress < 1.0 {
-        progress = (start_time.elapsed().as_secs_f64() / total_duration.as_secs_f64()).min(1.0);
+    while progress < 1.0 && !position.is_finished() {
+        let elapsed = position.position();
+        progress = (elapsed.as_secs_f64() / total_duration.as_secs_f64()).min(1.0);
         progress_tx
             .send(Event::SoundProgress(progress))
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
         progress_tx
             .send(Event::ProgressLabel(
-                format_time(start_time.elapsed().as_secs(), total_duration.as_secs()),
+                format_time(elapsed.as_secs(), total_duration.as_secs()),
                 false,
             ))
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
         thread::sleep(Duration::from_millis(100));
     }
     progress_tx
-        .send(Event::ProgressLabel(
-            "Press <P> to play the sound".to_string(),
-            true,
+        .send(Event::ProgressLabel(idle_label, true))
+        .map_err(io::Error::other)?;
+
+    Ok(())
+}
+
+fn find_wav_files(
+    root: &Path,
+    dir: &Path,
+    skipped_dir: &Path,
+    files: &mut Vec<String>,
+) -> io::Result<()> {
+    // Walks the subdirectories as well, the names are relative to the root
+    // The output directory is skipped, so the denoised files don't show up as inputs
+    let entries = fs::read_dir(dir).map_err(|e| {
+        io::Error::other(format!(
+            "Failed to read directory '{}': {}",
+            dir.display(),
+            e
         ))
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    })?;
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+
+        let path = entry.path();
+        if path.is_dir() {
+            if path != skipped_dir {
+                find_wav_files(root, &path, skipped_dir, files)?;
+            }
+        } else if path.extension().and_then(|s| s.to_str()) == Some("wav")
+            && let Some(name) = path.strip_prefix(root).ok().and_then(|n| n.to_str())
+        {
+            files.push(name.to_string());
+        }
+    }
 
     Ok(())
 }
 
-pub(crate) fn handle_input_events(tx: mpsc::Sender<Event>) {
+pub fn handle_input_events(tx: mpsc::Sender<Event>) {
     loop {
         match crossterm::event::read() {
             Ok(crossterm::event::Event::Key(key_event)) => {
@@ -158,21 +423,32 @@ pub(crate) fn handle_input_events(tx: mpsc::Sender<Event>) {
 
 impl App {
     pub fn new(tx: Sender<Event>) -> App {
+        let config = Config::default();
         Self {
             files: None,
             path: None,
+            output_path: None,
             selected: 0,
             exit: false,
             progress_bar_color: Color::Green,
             sound_progress: 0.0,
-            threshold: 0.01,
+            threshold: config.threshold,
+            thresholds: HashMap::new(),
             tx,
             sink_original: None,
             sink_denoised: None,
-            start_time: None,
+            sink_difference: None,
+            playing_file: None,
+            loaded_wav: None,
+            clipping_ratio: None,
+            playback_mode: PlaybackMode::Original,
+            crossfade_generation: Arc::new(AtomicUsize::new(0)),
+            playback_position: None,
             duration: None,
             ready_to_play: false,
-            label: String::from("Press <P> to play the sound"),
+            label: play_label(&config),
+            error: None,
+            config,
         }
     }
 
@@ -181,53 +457,86 @@ impl App {
         terminal: &mut DefaultTerminal,
         rx: mpsc::Receiver<Event>,
     ) -> io::Result<()> {
-        self.ensure_directories_exists()?;
+        self.apply_config(Config::load(Path::new(CONFIG_FILE))?);
+
+        let current_dir = env::current_dir()
+            .map_err(|e| io::Error::other(format!("Failed to get current directory: {}", e)))?;
+        self.ensure_directories_exists(&current_dir)?;
         self.list_wav_files()?;
+        if let Some(data_path) = &self.path {
+            self.thresholds = load_thresholds(data_path);
+        }
+        self.restore_threshold();
 
         while !self.exit {
             terminal.draw(|frame| self.draw(frame))?;
             match rx.recv() {
-                Ok(Event::Input(key_event)) => self.handle_key_event(key_event)?,
-                Ok(Event::SoundProgress(progress)) => self.sound_progress = progress,
-                Ok(Event::SinksReady(sink_orig, sink_denoised, start_time, duration)) => {
-                    self.sink_original = Some(sink_orig);
-                    self.sink_denoised = Some(sink_denoised);
-                    self.start_time = Some(start_time);
-                    self.duration = Some(duration);
-                    self.display_progress(start_time, duration);
-                }
-                Ok(Event::ProgressLabel(label, ready_to_play)) => {
-                    self.label = label;
-                    self.ready_to_play = ready_to_play;
-                }
+                Ok(event) => self.handle_event(event)?,
                 Err(e) => {
                     eprintln!("Event receive error: {:?}", e);
                     break;
                 }
             }
         }
+
+        if let Some(data_path) = &self.path {
+            save_thresholds(data_path, &self.thresholds)?;
+        }
         Ok(())
     }
 
-    fn ensure_directories_exists(&mut self) -> io::Result<()> {
-        let current_dir = env::current_dir().map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Failed to get current directory: {}", e),
-            )
-        })?;
+    fn apply_config(&mut self, config: Config) {
+        self.threshold = config.threshold;
+        self.label = play_label(&config);
+        self.config = config;
+    }
 
-        let data_dir = current_dir.join("data");
-        let denoised_dir = data_dir.join("denoised");
+    fn handle_event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Input(key_event) => self.handle_key_event(key_event)?,
+            Event::SoundProgress(progress) => self.sound_progress = progress,
+            Event::SinksReady(sink_orig, sink_denoised, sink_difference, position, duration) => {
+                self.sink_original = Some(Arc::new(sink_orig));
+                self.sink_denoised = Some(Arc::new(sink_denoised));
+                self.sink_difference = Some(Arc::new(sink_difference));
+                self.playback_position = Some(position.clone());
+                self.duration = Some(duration);
+                self.display_progress(position, duration);
+            }
+            Event::FileLoaded(filename, wav, clipping_ratio) => {
+                self.loaded_wav = Some((filename, wav));
+                self.clipping_ratio = Some(clipping_ratio);
+            }
+            Event::ProgressLabel(label, ready_to_play) => {
+                self.label = label;
+                self.ready_to_play = ready_to_play;
+            }
+            Event::Error(message) => {
+                // Whatever was in progress has failed, so playing again is allowed
+                self.error = Some(message);
+                self.ready_to_play = true;
+                self.label = play_label(&self.config);
+            }
+        }
+        Ok(())
+    }
+
+    fn ensure_directories_exists(&mut self, root: &Path) -> io::Result<()> {
+        let data_dir = root.join(&self.config.input_dir);
+        let denoised_dir = root.join(&self.config.output_dir);
 
-        fs::create_dir_all(&denoised_dir).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Failed to create 'data/denoised' directory: {}", e),
-            )
-        })?;
+        for dir in [&data_dir, &denoised_dir] {
+            fs::create_dir_all(dir).map_err(|e| {
+                io::Error::other(format!(
+                    "Failed to create '{}' directory: {}",
+                    dir.display(),
+                    e
+                ))
+            })?;
+        }
 
         self.path = Some(data_dir);
+        self.output_path = Some(denoised_dir);
         Ok(())
     }
 
@@ -237,28 +546,14 @@ impl App {
             .clone()
             .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Data path not set"))?;
 
-        let entries = fs::read_dir(&data_path).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Failed to read directory '{}': {}", data_path.display(), e),
-            )
-        })?;
+        let output_path = self.output_path.clone().unwrap_or_default();
 
         let mut files: Vec<String> = vec![];
+        find_wav_files(&data_path, &data_path, &output_path, &mut files)?;
+        files.sort();
 
-        for entry in entries {
-            let entry = match entry {
-                Ok(e) => e,
-                Err(_) => continue,
-            };
-
-            let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("wav") {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    files.push(name.to_string());
-                    self.ready_to_play = true;
-                }
-            }
+        if !files.is_empty() {
+            self.ready_to_play = true;
         }
 
         if files.is_empty() {
@@ -273,78 +568,204 @@ impl App {
         frame.render_widget(self, frame.area())
     }
 
-    fn display_progress(&mut self, start_time: Instant, duration: Duration) {
+    fn display_progress(&mut self, position: PlaybackPosition, duration: Duration) {
         let progress_tx = self.tx.clone();
+        let error_tx = self.tx.clone();
+        let idle_label = play_label(&self.config);
         thread::spawn(move || {
-            if let Err(e) = load_progress_bar(progress_tx, start_time, duration) {
-                eprintln!("Progress bar error: {:?}", e);
+            if let Err(e) = load_progress_bar(progress_tx, position, duration, idle_label) {
+                let _ = error_tx.send(Event::Error(format!("Progress bar error: {}", e)));
             }
         });
     }
 
     fn next(&mut self) {
-        if let Some(files) = &self.files {
-            if self.selected + 1 < files.len() {
-                self.selected += 1;
-            }
+        if let Some(files) = &self.files
+            && self.selected + 1 < files.len()
+        {
+            self.selected += 1;
         }
+        self.restore_threshold();
     }
 
     fn previous(&mut self) {
         if self.selected > 0 {
             self.selected -= 1;
         }
+        self.restore_threshold();
+    }
+
+    fn remember_threshold(&mut self) {
+        // Placeholder message shown when there are no files is not remembered
+        let (Some(path), Some(filename)) = (&self.path, self.selected_file()) else {
+            return;
+        };
+        if path.join(filename).is_file() {
+            self.thresholds.insert(filename.clone(), self.threshold);
+        }
+    }
+
+    fn restore_threshold(&mut self) {
+        // Files that were never tuned keep whatever threshold is currently set
+        if let Some(&threshold) = self
+            .selected_file()
+            .and_then(|filename| self.thresholds.get(filename))
+        {
+            self.threshold = threshold;
+        }
     }
 
     fn selected_file(&self) -> Option<&String> {
         self.files.as_ref()?.get(self.selected)
     }
 
+    fn loaded_wav(&self, filename: &String) -> Option<Arc<WavFile>> {
+        match &self.loaded_wav {
+            Some((loaded, wav)) if loaded == filename => Some(wav.clone()),
+            _ => None,
+        }
+    }
+
+    fn redenoise_playing(&self) {
+        // Only the file that is currently playing can be swapped in place
+        let (Some(orig), Some(denoised), Some(difference)) = (
+            &self.sink_original,
+            &self.sink_denoised,
+            &self.sink_difference,
+        ) else {
+            return;
+        };
+        let Some(filename) = self.playing_file.clone() else {
+            return;
+        };
+        let Some(wav) = self.loaded_wav(&filename) else {
+            return;
+        };
+        let error_tx = self.tx.clone();
+        let output_path = self.output_path.clone().unwrap();
+        let threshold = self.threshold;
+        let (orig, denoised, difference) = (orig.clone(), denoised.clone(), difference.clone());
+        thread::spawn(move || {
+            if let Err(e) = redenoise(
+                wav,
+                output_path,
+                &filename,
+                threshold,
+                orig,
+                denoised,
+                difference,
+            ) {
+                let _ = error_tx.send(Event::Error(format!("Re-denoise error: {}", e)));
+            }
+        });
+    }
+
     fn handle_key_event(&mut self, key_event: crossterm::event::KeyEvent) -> io::Result<()> {
+        // Any key dismisses the error popup, without doing anything else
+        if key_event.is_press() && self.error.is_some() {
+            self.error = None;
+            return Ok(());
+        }
+
         if key_event.is_press() {
             match key_event.code {
-                crossterm::event::KeyCode::Char('q') => self.exit = true,
-                crossterm::event::KeyCode::Char('p') => {
-                    if self.ready_to_play {
-                        self.ready_to_play = false;
-                        self.sound_progress = 0.0;
-                        self.progress_bar_color = Color::Green;
-                        self.sink_original = None;
-                        self.sink_denoised = None;
-                        self.label = String::from("Denoising...");
-                        let playback_tx = self.tx.clone(); // need to play file in a thread
-                        let file_path = self.path.clone().unwrap();
-                        let filename = self.selected_file().unwrap().clone();
-                        let threshold = self.threshold.clone();
+                crossterm::event::KeyCode::Char(c) if c == self.config.keys.quit => {
+                    self.exit = true
+                }
+                crossterm::event::KeyCode::Char(c)
+                    if c == self.config.keys.play && self.ready_to_play =>
+                {
+                    self.ready_to_play = false;
+                    self.sound_progress = 0.0;
+                    self.progress_bar_color = Color::Green;
+                    self.sink_original = None;
+                    self.sink_denoised = None;
+                    self.sink_difference = None;
+                    self.playback_mode = PlaybackMode::Original;
+                    self.label = String::from("Denoising...");
+                    let playback_tx = self.tx.clone(); // need to play file in a thread
+                    let error_tx = self.tx.clone();
+                    let file_path = self.path.clone().unwrap();
+                    let output_path = self.output_path.clone().unwrap();
+                    let filename = self.selected_file().unwrap().clone();
+                    let threshold = self.threshold;
+                    let cached_wav = self.loaded_wav(&filename);
+                    self.playing_file = Some(filename.clone());
+                    thread::spawn(move || {
+                        if let Err(e) = play_file(
+                            playback_tx,
+                            file_path,
+                            output_path,
+                            &filename,
+                            threshold,
+                            cached_wav,
+                            open_output,
+                        ) {
+                            let _ = error_tx.send(Event::Error(format!("Playback error: {}", e)));
+                        }
+                    });
+                }
+                crossterm::event::KeyCode::Char(c)
+                    if c == self.config.keys.save && self.ready_to_play =>
+                {
+                    self.ready_to_play = false;
+                    self.label = String::from("Denoising...");
+                    let save_tx = self.tx.clone();
+                    let error_tx = self.tx.clone();
+                    let file_path = self.path.clone().unwrap();
+                    let output_path = self.output_path.clone().unwrap();
+                    let filename = self.selected_file().unwrap().clone();
+                    let threshold = self.threshold;
+                    let cached_wav = self.loaded_wav(&filename);
+                    let saved_label = format!(
+                        "Saved to {}",
+                        self.config.output_dir.join(&filename).display()
+                    );
+                    thread::spawn(move || {
+                        if let Err(e) = save_file(
+                            save_tx,
+                            file_path,
+                            output_path,
+                            &filename,
+                            threshold,
+                            cached_wav,
+                            saved_label,
+                        ) {
+                            let _ = error_tx.send(Event::Error(format!("Save error: {}", e)));
+                        }
+                    });
+                }
+                crossterm::event::KeyCode::Char(c) if c == self.config.keys.toggle => {
+                    if let (Some(orig), Some(denoised), Some(difference)) = (
+                        &self.sink_original,
+                        &self.sink_denoised,
+                        &self.sink_difference,
+                    ) {
+                        self.playback_mode = self.playback_mode.next();
+                        self.progress_bar_color = self.playback_mode.color();
+
+                        let id = self.crossfade_generation.fetch_add(1, Ordering::SeqCst) + 1;
+                        let generation = self.crossfade_generation.clone();
+                        let sinks = [orig.clone(), denoised.clone(), difference.clone()];
+                        let mode = self.playback_mode;
                         thread::spawn(move || {
-                            if let Err(e) = play_file(playback_tx, file_path, &filename, threshold)
-                            {
-                                eprintln!("Playback thread error: {:?}", e);
-                            }
+                            crossfade(sinks, mode, generation, id);
                         });
                     }
                 }
-                crossterm::event::KeyCode::Char('c') => {
-                    if let (Some(orig), Some(denoised)) = (&self.sink_original, &self.sink_denoised)
-                    {
-                        if orig.volume() > 0.0 {
-                            orig.set_volume(0.0);
-                            denoised.set_volume(1.0);
-                            self.progress_bar_color = Color::Red;
-                        } else {
-                            orig.set_volume(1.0);
-                            denoised.set_volume(0.0);
-                            self.progress_bar_color = Color::Green;
-                        }
-                    }
+                crossterm::event::KeyCode::Char(c) if c == self.config.keys.redenoise => {
+                    self.redenoise_playing();
                 }
                 crossterm::event::KeyCode::Down => self.next(),
                 crossterm::event::KeyCode::Up => self.previous(),
                 crossterm::event::KeyCode::Left => {
-                    self.threshold = (self.threshold - 0.01).max(0.0);
+                    self.threshold = (self.threshold - self.config.threshold_step).max(0.0);
+                    self.remember_threshold();
                 }
                 crossterm::event::KeyCode::Right => {
-                    self.threshold = (self.threshold + 0.01).min(0.1);
+                    self.threshold = (self.threshold + self.config.threshold_step)
+                        .min(self.config.threshold_max);
+                    self.remember_threshold();
                 }
                 _ => {}
             }
@@ -365,9 +786,13 @@ impl Widget for &App {
             " Change File ".into(),
             "<Up/Down>".red().bold(),
             " Play ".into(),
-            "<P>".red().bold(),
+            key_label(self.config.keys.play).red().bold(),
+            " Save ".into(),
+            key_label(self.config.keys.save).red().bold(),
             " Quit ".into(),
-            "<Q> ".red().bold(),
+            format!("{} ", key_label(self.config.keys.quit))
+                .red()
+                .bold(),
         ])
         .bold()
         .centered();
@@ -387,23 +812,37 @@ impl Widget for &App {
         let file_selector = List::new(items)
             .block(controls_block)
             .highlight_style(Style::default().fg(Color::Yellow))
-            .bg(Color::Indexed(017))
+            .bg(Color::Indexed(17))
             .highlight_symbol(">> ");
 
         let mut state = ListState::default();
         state.select(Some(self.selected));
 
         let instructions = Line::from(vec![
-            " Change to original/denoised ".into(),
-            " <C> ".blue().bold(),
+            " Change to original/denoised/noise ".into(),
+            format!(" {} ", key_label(self.config.keys.toggle))
+                .blue()
+                .bold(),
+            " Re-denoise ".into(),
+            format!(" {} ", key_label(self.config.keys.redenoise))
+                .blue()
+                .bold(),
         ])
         .centered();
 
-        let sound_controls_block = Block::bordered()
+        let mut sound_controls_block = Block::bordered()
             .title(" Sound Track ")
             .title_bottom(instructions)
             .borders(Borders::ALL)
             .border_set(border::THICK);
+        if let Some(ratio) = self.clipping_ratio.filter(|&ratio| ratio > 0.0) {
+            sound_controls_block = sound_controls_block.title(
+                Line::from(format!(" Clipped: {:.2}% ", ratio * 100.0))
+                    .red()
+                    .bold()
+                    .right_aligned(),
+            );
+        }
 
         let progress_bar = Gauge::default()
             .gauge_style(Style::default().fg(self.progress_bar_color))
@@ -412,7 +851,7 @@ impl Widget for &App {
             .ratio(self.sound_progress);
 
         let threshold_instructions = Line::from(vec![
-            " +0.01 / -0.01 ".into(),
+            format!(" +{0:.2} / -{0:.2} ", self.config.threshold_step).into(),
             " <Left>/<Right> ".blue().bold(),
         ])
         .centered();
@@ -427,7 +866,7 @@ impl Widget for &App {
             .gauge_style(Style::default().fg(Color::LightBlue))
             .block(threshold_control_block)
             .label(Span::raw(format!("Threshold: {:.2}", self.threshold)))
-            .ratio(self.threshold * 10.0);
+            .ratio(gauge_ratio(self.threshold, self.config.threshold_max));
 
         StatefulWidget::render(&file_selector, file_selection_area, buf, &mut state);
 
@@ -441,6 +880,304 @@ impl Widget for &App {
             buf,
         );
 
-        threshold_bar.render(threshold_area, buf)
+        threshold_bar.render(threshold_area, buf);
+
+        if let Some(error) = &self.error {
+            let popup_area = centered_rect(area, 60, 7);
+
+            let error_block = Block::bordered()
+                .title(" Error ")
+                .title_bottom(Line::from(" Press any key to close ").centered())
+                .borders(Borders::ALL)
+                .border_set(border::THICK)
+                .fg(Color::Red);
+
+            let error_popup = Paragraph::new(error.as_str())
+                .block(error_block)
+                .wrap(Wrap { trim: true })
+                .centered();
+
+            Clear.render(popup_area, buf);
+            error_popup.render(popup_area, buf);
+        }
+    }
+}
+
+fn centered_rect(area: Rect, width_percent: u16, height: u16) -> Rect {
+    let width = area.width * width_percent / 100;
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+fn gauge_ratio(value: f64, max: f64) -> f64 {
+    // Gauge panics on ratios outside [0, 1] - thresholds restored from thresholds.json
+    // or set in the config may lie above threshold_max, which may be 0 as well
+    let ratio = value / max;
+    if ratio.is_nan() {
+        0.0
+    } else {
+        ratio.clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::audio_samples::AudioSamples;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tui_app_test_{}_{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn copy_example(dir: &Path, name: &str) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::copy("data/voice_noised.wav", path).unwrap();
+    }
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    fn idle_sink(volume: f32) -> Arc<rodio::Sink> {
+        let (sink, _queue) = rodio::Sink::new_idle();
+        sink.set_volume(volume);
+        Arc::new(sink)
+    }
+
+    #[test]
+    fn crossfade_ramps_to_the_target() {
+        let steps = crossfade_steps(1.0, 0.0, CROSSFADE_STEPS);
+        assert_eq!(steps.len(), CROSSFADE_STEPS as usize);
+        assert!(steps.windows(2).all(|w| w[1] < w[0]));
+        assert_eq!(steps.last(), Some(&0.0));
+
+        let steps = crossfade_steps(0.25, 1.0, CROSSFADE_STEPS);
+        assert!(steps.windows(2).all(|w| w[1] > w[0]));
+        assert_eq!(steps.last(), Some(&1.0));
+
+        let sinks = [idle_sink(1.0), idle_sink(0.0), idle_sink(0.5)];
+        let generation = Arc::new(AtomicUsize::new(1));
+        crossfade(sinks.clone(), PlaybackMode::Denoised, generation, 1);
+        let volumes: Vec<f32> = sinks.iter().map(|sink| sink.volume()).collect();
+        assert_eq!(volumes, vec![0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn stale_crossfade_leaves_the_volumes() {
+        // A newer toggle bumped the generation, the older fade must not touch anything
+        let sinks = [idle_sink(1.0), idle_sink(0.0), idle_sink(0.0)];
+        let generation = Arc::new(AtomicUsize::new(2));
+        crossfade(sinks.clone(), PlaybackMode::Denoised, generation, 1);
+        let volumes: Vec<f32> = sinks.iter().map(|sink| sink.volume()).collect();
+        assert_eq!(volumes, vec![1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn thresholds_survive_a_restart() {
+        let dir = temp_dir("thresholds");
+        assert!(load_thresholds(&dir).is_empty());
+
+        let thresholds = HashMap::from([
+            ("a.wav".to_string(), 0.05),
+            ("sub/b.wav".to_string(), 0.125),
+        ]);
+        save_thresholds(&dir, &thresholds).unwrap();
+        assert_eq!(load_thresholds(&dir), thresholds);
+
+        fs::write(dir.join(THRESHOLDS_FILE), "{ not json").unwrap();
+        assert!(load_thresholds(&dir).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn config_lands_on_the_app() {
+        let config = Config::parse(
+            "threshold = 0.04\nthreshold_step = 0.02\nthreshold_max = 0.5\n\
+             input_dir = \"recordings\"\noutput_dir = \"clean\"\n[keys]\nplay = \"x\"",
+        )
+        .unwrap();
+        let (tx, _rx) = mpsc::channel();
+        let mut app = App::new(tx);
+        app.apply_config(config);
+        assert_eq!(app.threshold, 0.04);
+        assert_eq!(app.config.threshold_step, 0.02);
+        assert_eq!(app.config.threshold_max, 0.5);
+        assert_eq!(app.label, "Press <X> to play the sound");
+
+        let root = temp_dir("config");
+        app.ensure_directories_exists(&root).unwrap();
+        assert_eq!(app.path, Some(root.join("recordings")));
+        assert_eq!(app.output_path, Some(root.join("clean")));
+        assert!(root.join("clean").is_dir());
+
+        // The threshold moves by the configured step, up to the configured maximum
+        app.handle_key_event(KeyEvent::from(KeyCode::Right))
+            .unwrap();
+        assert!((app.threshold - 0.06).abs() < 1e-12);
+        app.threshold = 0.49;
+        app.handle_key_event(KeyEvent::from(KeyCode::Right))
+            .unwrap();
+        assert_eq!(app.threshold, 0.5);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn wav_files_are_found_in_subdirectories() {
+        let root = temp_dir("find");
+        copy_example(&root, "a.wav");
+        copy_example(&root, "nested/deeper/b.wav");
+        copy_example(&root, "denoised/a.wav");
+        fs::write(root.join("notes.txt"), "not audio").unwrap();
+        fs::write(root.join("nested/c.wav.bak"), "not audio").unwrap();
+
+        let mut files = Vec::new();
+        find_wav_files(&root, &root, &root.join("denoised"), &mut files).unwrap();
+        files.sort();
+        let expected = vec![
+            PathBuf::from("a.wav"),
+            Path::new("nested").join("deeper").join("b.wav"),
+        ];
+        assert_eq!(
+            files.iter().map(PathBuf::from).collect::<Vec<_>>(),
+            expected
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn errors_show_until_the_next_key() {
+        let (tx, _rx) = mpsc::channel();
+        let mut app = App::new(tx);
+        app.ready_to_play = false;
+        app.handle_event(Event::Error("Playback error: broken".to_string()))
+            .unwrap();
+        assert_eq!(app.error.as_deref(), Some("Playback error: broken"));
+        assert!(app.ready_to_play);
+
+        // The key only closes the popup, quitting takes another press
+        let quit = app.config.keys.quit;
+        app.handle_event(Event::Input(key(quit))).unwrap();
+        assert_eq!(app.error, None);
+        assert!(!app.exit);
+        app.handle_event(Event::Input(key(quit))).unwrap();
+        assert!(app.exit);
+    }
+
+    #[test]
+    fn loaded_file_is_cached() {
+        let dir = temp_dir("cache");
+        copy_example(&dir, "a.wav");
+        copy_example(&dir, "b.wav");
+        let (a, b) = ("a.wav".to_string(), "b.wav".to_string());
+        let (tx, rx) = mpsc::channel();
+        let mut app = App::new(tx.clone());
+
+        let first = load_wav(&tx, dir.clone(), &a, app.loaded_wav(&a)).unwrap();
+        app.handle_event(rx.try_recv().unwrap()).unwrap();
+        assert!(app.loaded_wav(&a).is_some());
+
+        // Parsed only once, the file is not even needed on the disk any more
+        fs::remove_file(dir.join(&a)).unwrap();
+        let second = load_wav(&tx, dir.clone(), &a, app.loaded_wav(&a)).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(rx.try_recv().is_err());
+
+        // Another file takes the place of the first one
+        assert!(app.loaded_wav(&b).is_none());
+        let other = load_wav(&tx, dir.clone(), &b, app.loaded_wav(&b)).unwrap();
+        app.handle_event(rx.try_recv().unwrap()).unwrap();
+        assert!(Arc::ptr_eq(&app.loaded_wav(&b).unwrap(), &other));
+        assert!(app.loaded_wav(&a).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn labels(rx: &mpsc::Receiver<Event>) -> Vec<(String, bool)> {
+        rx.try_iter()
+            .filter_map(|event| match event {
+                Event::ProgressLabel(label, ready_to_play) => Some((label, ready_to_play)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn without_an_audio_device_the_file_is_still_saved() {
+        let dir = temp_dir("no_device");
+        copy_example(&dir, "a.wav");
+        let output_path = dir.join("denoised");
+        let (tx, rx) = mpsc::channel();
+
+        play_file(
+            tx,
+            dir.clone(),
+            output_path.clone(),
+            &"a.wav".to_string(),
+            0.1,
+            None,
+            || None,
+        )
+        .unwrap();
+        assert!(WavFile::from_wav_file(output_path.join("a.wav").to_str().unwrap()).is_ok());
+        assert_eq!(labels(&rx), vec![(NO_AUDIO_DEVICE_LABEL.to_string(), true)]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn playback_modes_cycle() {
+        let modes = [
+            PlaybackMode::Original,
+            PlaybackMode::Denoised,
+            PlaybackMode::Difference,
+        ];
+        for (i, mode) in modes.iter().enumerate() {
+            assert_eq!(mode.next(), modes[(i + 1) % modes.len()]);
+            assert_eq!(*mode as usize, i);
+        }
+        assert_ne!(modes[0].color(), modes[1].color());
+        assert_ne!(modes[1].color(), modes[2].color());
+        assert_ne!(modes[2].color(), modes[0].color());
+    }
+
+    #[test]
+    fn gauge_ratio_stays_in_range() {
+        assert_eq!(gauge_ratio(0.05, 0.1), 0.5);
+        assert_eq!(gauge_ratio(0.1, 0.1), 1.0);
+        assert_eq!(gauge_ratio(0.3, 0.1), 1.0);
+        assert_eq!(gauge_ratio(-0.1, 0.1), 0.0);
+        assert_eq!(gauge_ratio(0.05, 0.0), 1.0);
+        assert_eq!(gauge_ratio(0.0, 0.0), 0.0);
+        assert_eq!(gauge_ratio(f64::NAN, 0.1), 0.0);
+        assert_eq!(gauge_ratio(f64::INFINITY, 0.1), 1.0);
+        assert_eq!(gauge_ratio(0.05, f64::INFINITY), 0.0);
+        assert_eq!(gauge_ratio(f64::INFINITY, f64::INFINITY), 0.0);
+    }
+
+    #[test]
+    fn denoised_file_is_saved_in_the_same_format() {
+        let dir = temp_dir("save");
+        let samples: Vec<[i16; 2]> = (0..3000)
+            .map(|i| [(i % 50 * 300) as i16, (i % 70 * -200) as i16])
+            .collect();
+        let wav = WavFile::from_samples(AudioSamples::StereoI16(samples), 8000);
+
+        let filename = Path::new("sub").join("a.wav").to_string_lossy().to_string();
+        let denoised = denoise_and_save(&wav, &dir, &filename, 0.1).unwrap();
+        assert_ne!(denoised.data, wav.data);
+
+        let saved = WavFile::from_reader(fs::File::open(dir.join(&filename)).unwrap()).unwrap();
+        assert_eq!(saved.fmt, wav.fmt);
+        assert_eq!(saved.num_frames(), wav.num_frames());
+        assert_eq!(saved.data, denoised.data);
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,19 +1,37 @@
 use crate::models::audio_samples::AudioSamples;
+use crate::models::biquad::{BiquadCoeffs, filtfilt};
+use crate::models::denoise::{
+    ChannelSpectrum, DenoiseReport, FftLength, WindowKind, declick, denoise_signal,
+    denoise_signal_weighted, denoise_signal_windowed, denoise_signal_with_spectrum,
+    low_pass_filter, median_filter, moving_average, noise_psd, notch_filter, phase_mask,
+    sliding_minimum, smooth_spectral_gate, spectrogram, stft_denoise, wiener_filter,
+};
 use crate::models::errors::WavError;
-use crate::models::fft::{fft_real_zero_padded, ifft};
+use crate::models::fft::{bin_to_hz, fft_real_zero_padded};
+use serde::{Serialize, Serializer};
+use std::f64::consts::PI;
 use std::fmt::Display;
 use std::fs;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::time::Duration;
 
 // The Scriptures:
 // http://soundfile.sapp.org/doc/WaveFormat/
 
 // Display implementations done using chat
 
-#[derive(Debug, Clone)]
-pub(crate) struct WavHead {
+// Chunk ids go into the JSON metadata as text, same as in the Display implementations
+fn serialize_chunk_id<S: Serializer>(id: &[u8; 4], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(std::str::from_utf8(id).unwrap_or("????"))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WavHead {
+    #[serde(serialize_with = "serialize_chunk_id")]
     pub chunk_id: [u8; 4],
     pub chunk_size: u32,
+    #[serde(serialize_with = "serialize_chunk_id")]
     pub format: [u8; 4],
 }
 
@@ -29,8 +47,9 @@ impl Display for WavHead {
     }
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct WavFmt {
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WavFmt {
+    #[serde(serialize_with = "serialize_chunk_id")]
     pub subchunk_id: [u8; 4],
     pub subchunk_size: u32,
     pub audio_format: AudioFormat,
@@ -39,6 +58,18 @@ pub(crate) struct WavFmt {
     pub byte_rate: u32,
     pub block_align: u16,
     pub bits_per_sample: u16,
+    pub extension: Option<WavFmtExtension>,
+    // Everything past BitsPerSample (cbSize and the extension), kept so that it can be written back
+    pub extra: Vec<u8>,
+}
+
+// Additional fields of WAVE_FORMAT_EXTENSIBLE, used by multichannel and high bit depth files
+// The actual format is stored in the first two bytes of sub_format
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WavFmtExtension {
+    pub valid_bits_per_sample: u16,
+    pub channel_mask: u32,
+    pub sub_format: [u8; 16],
 }
 
 impl Display for WavFmt {
@@ -58,10 +89,13 @@ impl Display for WavFmt {
     }
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct WavData {
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WavData {
+    #[serde(serialize_with = "serialize_chunk_id")]
     pub subchunk_id: [u8; 4],
     pub subchunk_size: u32,
+    // The samples are left out, only the sizes go into the metadata
+    #[serde(skip)]
     pub data: AudioSamples,
 }
 
@@ -76,21 +110,61 @@ impl Display for WavData {
     }
 }
 
-#[derive(Debug, Clone)]
-pub(crate) enum AudioFormat {
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum AudioFormat {
     Pcm,
+    IeeeFloat,
     Other(u16),
 }
 
 impl AudioFormat {
     fn value(&self) -> u16 {
         match self {
-            AudioFormat::Pcm => 1 as u16,
+            AudioFormat::Pcm => 1,
+            AudioFormat::IeeeFloat => 3,
             AudioFormat::Other(x) => *x,
         }
     }
+
+    fn from_value(value: u16) -> AudioFormat {
+        match value {
+            1 => AudioFormat::Pcm,
+            3 => AudioFormat::IeeeFloat,
+            x => AudioFormat::Other(x),
+        }
+    }
+}
+
+// Everything about the file but the samples themselves, see WavFile::metadata_json
+#[derive(Serialize)]
+struct WavMetadata<'a> {
+    head: &'a WavHead,
+    fmt: &'a WavFmt,
+    fact: Option<&'a WavFact>,
+    data: &'a WavData,
+    duration_secs: f64,
+    num_frames: usize,
+    peak: Vec<f64>,
+    rms: Vec<f64>,
 }
 
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+const CLIPPING_LEVEL: f64 = 0.99;
+
+const LIMITER_LOOKAHEAD: Duration = Duration::from_millis(5);
+const LIMITER_RELEASE: Duration = Duration::from_millis(50);
+
+// Where normalize_to_rms limits the peaks, just under what clipping_ratio counts as clipped
+const NORMALIZE_CEILING: f64 = 0.98;
+
+const NOISE_FLOOR_SMOOTHING: Duration = Duration::from_millis(20);
+
+// Every KSDATAFORMAT_SUBTYPE_* GUID ends with these, the format tag is stored before them
+const SUB_FORMAT_GUID_SUFFIX: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
 // Offset  Size  Name             Description
 // 0         4   ChunkID          Contains the letters "RIFF" in ASCII form
 //                                (0x52494646 big-endian form).
@@ -144,6 +218,8 @@ pub fn new_fmt(num_channels: u16, sample_rate: u32, bits_per_sample: u16) -> Wav
         byte_rate,
         block_align,
         bits_per_sample,
+        extension: None,
+        extra: Vec::new(),
     }
 }
 
@@ -165,66 +241,251 @@ pub fn new_data(subchunk_size: u32, data: AudioSamples) -> WavData {
     }
 }
 
-#[derive(Debug, Clone)]
+// Offset  Size  Name             Description
+// 0         4   ChunkID          Contains the letters "fact"
+// 4         4   ChunkSize        4
+// 8         4   SampleLength     Number of samples in every channel
+//                                Required for every format but PCM, where the number cannot
+//                                be worked out from the size of the data (e.g. compressed ones)
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WavFact {
+    #[serde(serialize_with = "serialize_chunk_id")]
+    pub subchunk_id: [u8; 4],
+    pub subchunk_size: u32,
+    pub sample_length: u32,
+}
+
+pub fn new_fact(sample_length: u32) -> WavFact {
+    WavFact {
+        subchunk_id: *b"fact",
+        subchunk_size: 4,
+        sample_length,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct WavFile {
     pub head: WavHead,
     pub fmt: WavFmt,
+    // Only present in the file if it was read with one, or the format is not PCM
+    pub fact: Option<WavFact>,
     pub data: WavData,
+    // Tags of the LIST/INFO chunk (IART, INAM, ICMT...), written back after the data
+    pub info: Vec<([u8; 4], String)>,
+}
+
+// One line summary, e.g. "WAV 44100Hz 16-bit stereo, 3.42s, 150840 frames"
+impl Display for WavFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let frames = self.num_frames();
+        let seconds = frames as f64 / self.fmt.sample_rate.max(1) as f64;
+        let channels = match self.fmt.num_channels {
+            1 => "mono".to_string(),
+            2 => "stereo".to_string(),
+            n => format!("{}-channel", n),
+        };
+        write!(
+            f,
+            "WAV {}Hz {}-bit {}, {:.2}s, {} frames",
+            self.fmt.sample_rate, self.fmt.bits_per_sample, channels, seconds, frames
+        )
+    }
 }
 
 impl WavFile {
     // STRUCT READING FROM FILE
 
     pub fn from_wav_file(file_path: &str) -> Result<WavFile, WavError> {
+        Self::read_wav_file(file_path, false)
+    }
+
+    pub fn from_wav_file_strict(file_path: &str) -> Result<WavFile, WavError> {
+        // Same as above, but a fmt chunk contradicting itself is treated as a corrupted file
+        Self::read_wav_file(file_path, true)
+    }
+
+    fn read_wav_file(file_path: &str, strict: bool) -> Result<WavFile, WavError> {
+        let path = Path::new(file_path);
+        let data: Vec<u8> = fs::read(path).map_err(WavError::IoError)?;
+        Self::parse_wav_bytes(&data, strict)
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<WavFile, WavError> {
+        // Streams like stdin cannot seek, so the whole file is buffered before parsing
+        let mut data: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut data).map_err(WavError::IoError)?;
+        Self::parse_wav_bytes(&data, false)
+    }
+
+    pub fn validate(file_path: &str) -> Result<(), WavError> {
+        // Same checks as reading the file, but without loading the samples - only the chunk
+        // headers and the fmt chunk are read, everything else is skipped over
+        // Returns the error reading the file would fail with, or EmptyAudioData
+        let mut file = fs::File::open(file_path).map_err(WavError::IoError)?;
+        let file_len = file.metadata().map_err(WavError::IoError)?.len();
+
+        // The RIFF header, the fmt chunk and an empty data chunk are enough to parse the format
+        let mut skeleton: Vec<u8> = Vec::new();
+        Read::by_ref(&mut file)
+            .take(12)
+            .read_to_end(&mut skeleton)
+            .map_err(WavError::IoError)?;
+        Self::check_magic(&skeleton)?;
+
+        let mut data_size = None;
+        let mut offset: u64 = 12;
+        while offset < file_len {
+            let mut header: Vec<u8> = Vec::new();
+            Read::by_ref(&mut file)
+                .take(8)
+                .read_to_end(&mut header)
+                .map_err(WavError::IoError)?;
+            if header.len() < 8 {
+                break;
+            }
+            let chunk_size =
+                u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+
+            if header[..4] == *b"fmt " {
+                skeleton.extend_from_slice(&header);
+                Read::by_ref(&mut file)
+                    .take(chunk_size)
+                    .read_to_end(&mut skeleton)
+                    .map_err(WavError::IoError)?;
+                if chunk_size % 2 == 1 {
+                    skeleton.push(0);
+                }
+            } else if header[..4] == *b"data" && data_size.is_none() {
+                // Clamped to the end of the file, as when reading
+                data_size = Some(chunk_size.min(file_len - offset - 8));
+                skeleton.extend_from_slice(b"data");
+                skeleton.extend_from_slice(&0u32.to_le_bytes());
+            }
+
+            offset = offset.saturating_add(8 + chunk_size + chunk_size % 2);
+            file.seek(SeekFrom::Start(offset))
+                .map_err(WavError::IoError)?;
+        }
+
+        let wav = Self::parse_wav_bytes(&skeleton, false)?;
+        wav.check_pcm()?;
+        let frames = data_size.unwrap_or(0) / wav.fmt.block_align as u64;
+        if frames == 0 {
+            return Err(WavError::EmptyAudioData);
+        }
+        Ok(())
+    }
+
+    fn check_magic(data: &[u8]) -> Result<(), WavError> {
+        // Anything that is not RIFF at all (an MP3, a text file...) is turned away right at
+        // the start, instead of failing somewhere while looking for chunks in it
+        // RIFX and RF64 are WAV variants, those get the more specific errors of get_head_chunk
+        let magic = data.get(..4).ok_or(WavError::UnexpectedLength)?;
+        match magic {
+            b"RIFF" | b"RIFX" | b"RF64" => Ok(()),
+            _ => Err(WavError::NotAWavFile(magic.to_vec())),
+        }
+    }
+
+    fn parse_wav_bytes(data: &[u8], strict: bool) -> Result<WavFile, WavError> {
+        Self::check_magic(data)?;
+
         // Helper functions
 
         // Lifetime parameter
-        // Telling rust copmiler that "data" and returned slice will live at least as long as 'a
-        fn find_chunk<'a>(data: &'a [u8], chunk_id: &'a [u8; 4]) -> Option<&'a [u8]> {
+        // Telling rust copmiler that "data" and returned slices will live at least as long as 'a
+        // Every chunk after the RIFF header, including its id and size
+        fn chunks(data: &[u8]) -> Vec<&[u8]> {
+            let mut chunks = Vec::new();
             let mut offset = 12;
 
             // Get the next chunk's id and size
             // The first 4 bytes - chunk's id
             // The bytes from 5 to 8 - chunk's size
             // The bytes are also encoded in little-endian, so the from_le_bytes is needed
-            while offset + 8 < data.len() {
-                let id = &data[offset..offset + 4];
-                let chunk_size =
-                    u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
-
-                if id == chunk_id {
-                    let end = offset + 8 + chunk_size;
-                    if end <= data.len() {
-                        return Some(&data[offset..end]);
-                    }
-                    return None;
-                }
-                offset += 8 + chunk_size;
+            // A file truncated in the middle of a chunk header ends the search
+            while let Some(size_bytes) = data.get(offset + 4..offset + 8) {
+                let chunk_size = u32::from_le_bytes([
+                    size_bytes[0],
+                    size_bytes[1],
+                    size_bytes[2],
+                    size_bytes[3],
+                ]) as usize;
+
+                // The declared sizes cannot be trusted - streaming encoders write placeholders
+                // like 0xFFFFFFFF, since they don't know the length up front
+                // The end of the chunk is therefore clamped to the end of the file
+                let end = offset
+                    .saturating_add(8)
+                    .saturating_add(chunk_size)
+                    .min(data.len());
+                chunks.push(&data[offset..end]);
+
+                // Chunks of odd size are followed by a padding byte not counted in the size
+                // Skipping it keeps the chunks after them (e.g. fmt after 8-bit data) reachable
+                offset = end.saturating_add(chunk_size & 1);
             }
-            None
+            chunks
+        }
+
+        // Chunks other than the requested one are skipped, including the JUNK and PAD chunks
+        // inserted only for alignment - they are not kept, the file is written without them
+        fn find_chunk<'a>(data: &'a [u8], chunk_id: &'a [u8; 4]) -> Option<&'a [u8]> {
+            chunks(data)
+                .into_iter()
+                .find(|chunk| chunk[..4] == *chunk_id)
         }
 
-        fn get_head_chunk(data: &Vec<u8>) -> Result<WavHead, WavError> {
-            let riff = &data[..4];
+        // Offset  Size  Name        Description
+        // 0         4   ChunkID     "LIST"
+        // 4         4   ChunkSize
+        // 8         4   ListType    "INFO", other lists (e.g. "adtl" with cue labels) are skipped
+        // 12        *   Tags        Each one is an id, a size and NUL terminated text,
+        //                           padded to an even length like any other chunk
+        fn get_info_subchunk(data: &[u8]) -> Vec<([u8; 4], String)> {
+            let Some(list) = chunks(data)
+                .into_iter()
+                .find(|chunk| chunk[..4] == *b"LIST" && chunk.get(8..12) == Some(b"INFO"))
+            else {
+                return Vec::new();
+            };
+
+            // The tags are laid out the same as chunks in a RIFF file, after 12 bytes of headers
+            chunks(list)
+                .into_iter()
+                .filter(|tag| tag.len() >= 8)
+                .map(|tag| {
+                    let id = [tag[0], tag[1], tag[2], tag[3]];
+                    let text = String::from_utf8_lossy(&tag[8..]);
+                    (id, text.trim_end_matches('\0').to_string())
+                })
+                .collect()
+        }
+
+        fn get_head_chunk(data: &[u8]) -> Result<WavHead, WavError> {
+            // Files shorter than the header itself are rejected before slicing into them
+            let riff = data.get(..4).ok_or(WavError::UnexpectedLength)?;
             if riff != b"RIFF" {
                 return Err(WavError::InvalidRiffHeader(riff.to_vec()));
             }
-            let wave = &data[8..12];
+            let wave = data.get(8..12).ok_or(WavError::UnexpectedLength)?;
             if wave != b"WAVE" {
                 return Err(WavError::InvalidWaveFormat(wave.to_vec()));
             }
 
-            let wav_head = new_head(data.len() as u32 - 8);
+            // The size is recomputed once it is known which chunks are kept
+            let wav_head = new_head(0);
             Ok(wav_head)
         }
 
-        pub fn get_fmt_subchunk(data: &Vec<u8>) -> Result<WavFmt, WavError> {
+        pub fn get_fmt_subchunk(data: &[u8], strict: bool) -> Result<WavFmt, WavError> {
             let fmt_subchunk = find_chunk(data, b"fmt ").ok_or(WavError::UnexpectedLength)?;
             if fmt_subchunk.len() < 24 {
                 return Err(WavError::UnexpectedLength);
             }
 
-            let wav_fmt = new_fmt(
+            let mut wav_fmt = new_fmt(
                 u16::from_le_bytes([fmt_subchunk[10], fmt_subchunk[11]]),
                 u32::from_le_bytes([
                     fmt_subchunk[12],
@@ -235,13 +496,106 @@ impl WavFile {
                 u16::from_le_bytes([fmt_subchunk[22], fmt_subchunk[23]]),
             );
 
+            // ByteRate and BlockAlign are derived from the other fields by new_fmt,
+            // the values stored in the file are only compared against them in strict mode
+            let stored_byte_rate = u32::from_le_bytes([
+                fmt_subchunk[16],
+                fmt_subchunk[17],
+                fmt_subchunk[18],
+                fmt_subchunk[19],
+            ]);
+            let stored_block_align = u16::from_le_bytes([fmt_subchunk[20], fmt_subchunk[21]]);
+
+            // Headers longer than 16 bytes (18 with cbSize, 40 for WAVE_FORMAT_EXTENSIBLE)
+            // are kept as they are, some programs rely on the extension
+            wav_fmt.extra = fmt_subchunk[24..].to_vec();
+            wav_fmt.subchunk_size = 16 + wav_fmt.extra.len() as u32;
+
+            // Offset  Size  Name                 Description
+            // 24        2   cbSize               Size of the extension (22)
+            // 26        2   ValidBitsPerSample   At most BitsPerSample
+            // 28        4   ChannelMask          Speaker positions
+            // 32       16   SubFormat            GUID, starting with the actual format tag
+            let audio_format = u16::from_le_bytes([fmt_subchunk[8], fmt_subchunk[9]]);
+            wav_fmt.audio_format = AudioFormat::from_value(audio_format);
+            if audio_format == WAVE_FORMAT_EXTENSIBLE {
+                if fmt_subchunk.len() < 48 {
+                    return Err(WavError::UnexpectedLength);
+                }
+
+                let mut sub_format = [0u8; 16];
+                sub_format.copy_from_slice(&fmt_subchunk[32..48]);
+
+                wav_fmt.audio_format = if sub_format[2..] == SUB_FORMAT_GUID_SUFFIX {
+                    AudioFormat::from_value(u16::from_le_bytes([sub_format[0], sub_format[1]]))
+                } else {
+                    AudioFormat::Other(WAVE_FORMAT_EXTENSIBLE)
+                };
+                wav_fmt.extension = Some(WavFmtExtension {
+                    valid_bits_per_sample: u16::from_le_bytes([fmt_subchunk[26], fmt_subchunk[27]]),
+                    channel_mask: u32::from_le_bytes([
+                        fmt_subchunk[28],
+                        fmt_subchunk[29],
+                        fmt_subchunk[30],
+                        fmt_subchunk[31],
+                    ]),
+                    sub_format,
+                });
+            }
+
+            if wav_fmt.audio_format != AudioFormat::Pcm {
+                // Compressed formats pack samples into blocks of their own (e.g. 4-bit ADPCM),
+                // there is nothing to derive the values from - the stored ones are kept
+                wav_fmt.byte_rate = stored_byte_rate;
+                wav_fmt.block_align = stored_block_align;
+            } else if strict && stored_byte_rate != wav_fmt.byte_rate {
+                return Err(WavError::InconsistentFmt(format!(
+                    "byte rate {}, expected {}",
+                    stored_byte_rate, wav_fmt.byte_rate
+                )));
+            } else if strict && stored_block_align != wav_fmt.block_align {
+                return Err(WavError::InconsistentFmt(format!(
+                    "block align {}, expected {}",
+                    stored_block_align, wav_fmt.block_align
+                )));
+            }
+
             Ok(wav_fmt)
         }
 
-        fn get_data_subchunk(data: &Vec<u8>, fmt: &WavFmt) -> Result<WavData, WavError> {
+        fn get_fact_subchunk(data: &[u8]) -> Option<WavFact> {
+            // Anything after the first 4 bytes of the chunk is ignored, and written back as size 4
+            let fact_subchunk = find_chunk(data, b"fact")?;
+            let sample_length = fact_subchunk.get(8..12)?;
+            Some(new_fact(u32::from_le_bytes([
+                sample_length[0],
+                sample_length[1],
+                sample_length[2],
+                sample_length[3],
+            ])))
+        }
+
+        fn get_data_subchunk(data: &[u8], fmt: &WavFmt) -> Result<WavData, WavError> {
             let data_subchunk = find_chunk(data, b"data").ok_or(WavError::UnexpectedLength)?;
-            let subchunk_size = data_subchunk.len() as u32 - 8;
-            let audio_data = &data_subchunk[8..];
+
+            // Decoding anything but PCM as integers would only produce noise, the bytes are kept
+            // as they are instead - the file can be saved back, processing it fails in check_pcm
+            if fmt.audio_format != AudioFormat::Pcm {
+                let audio_data = &data_subchunk[8..];
+                return Ok(new_data(
+                    audio_data.len() as u32,
+                    AudioSamples::Encoded(audio_data.to_vec()),
+                ));
+            }
+
+            // A clamped chunk may end in the middle of a frame, the incomplete one is dropped
+            let block_align = fmt.block_align as usize;
+            if block_align == 0 {
+                return Err(WavError::UnexpectedLength);
+            }
+            let audio_length = (data_subchunk.len() - 8) / block_align * block_align;
+            let subchunk_size = audio_length as u32;
+            let audio_data = &data_subchunk[8..8 + audio_length];
 
             let data_field =
                 AudioSamples::from_le_bytes(audio_data, fmt.num_channels, fmt.bits_per_sample)?;
@@ -251,29 +605,121 @@ impl WavFile {
             Ok(wav_data)
         }
 
-        let path = Path::new(file_path);
-        let data: Vec<u8> = fs::read(path).map_err(WavError::IoError)?;
-
-        let header_chunk = get_head_chunk(&data)?;
-        let fmt_subchunk = get_fmt_subchunk(&data)?;
-        let data_subchunk = get_data_subchunk(&data, &fmt_subchunk)?;
+        let header_chunk = get_head_chunk(data)?;
+        let fmt_subchunk = get_fmt_subchunk(data, strict)?;
+        let data_subchunk = get_data_subchunk(data, &fmt_subchunk)?;
 
-        Ok(WavFile {
+        let mut wav = WavFile {
             head: header_chunk,
             fmt: fmt_subchunk,
+            fact: get_fact_subchunk(data),
             data: data_subchunk,
-        })
+            info: get_info_subchunk(data),
+        };
+        wav.update_sizes();
+        Ok(wav)
     }
 
     // STRUCT FROM SUBCHUNKS
 
     pub fn from_subchunks(head: WavHead, fmt: WavFmt, data: WavData) -> WavFile {
-        WavFile { head, fmt, data }
+        let mut wav = WavFile {
+            head,
+            fmt,
+            fact: None,
+            data,
+            info: Vec::new(),
+        };
+        // Adds the fact chunk formats other than PCM require
+        if wav.fmt.audio_format != AudioFormat::Pcm {
+            wav.update_sizes();
+        }
+        wav
+    }
+
+    // STRUCT GENERATED PROGRAMMATICALLY
+
+    pub fn from_samples(samples: AudioSamples, sample_rate: u32) -> WavFile {
+        // The format follows from the variant, the sizes from the number of samples
+        let num_channels = samples.num_channels();
+        let bits_per_sample = samples.bits_per_sample();
+
+        let mut wav = WavFile::from_subchunks(
+            new_head(0),
+            new_fmt(num_channels, sample_rate, bits_per_sample),
+            new_data(0, samples),
+        );
+        wav.update_sizes();
+        wav
+    }
+
+    pub fn from_pcm_bytes(
+        bytes: &[u8],
+        num_channels: u16,
+        sample_rate: u32,
+        bits_per_sample: u16,
+    ) -> Result<WavFile, WavError> {
+        // Raw PCM laid out like the contents of a data chunk, e.g. handed back by a decoder
+        // Trailing bytes of an incomplete frame would be silently dropped, so they are rejected
+        let block_align = num_channels as usize * bits_per_sample as usize / 8;
+        if block_align == 0 || !bytes.len().is_multiple_of(block_align) {
+            return Err(WavError::UnexpectedLength);
+        }
+
+        let samples = AudioSamples::from_le_bytes(bytes, num_channels, bits_per_sample)?;
+        Ok(Self::from_samples(samples, sample_rate))
+    }
+
+    pub fn silence(
+        duration: Duration,
+        sample_rate: u32,
+        num_channels: u16,
+        bits_per_sample: u16,
+    ) -> Result<WavFile, WavError> {
+        let frames = (duration.as_secs_f64() * sample_rate as f64).round() as usize;
+        let channels = vec![vec![0.0; frames]; num_channels as usize];
+        Self::from_normalized_channels(&channels, sample_rate, bits_per_sample)
+    }
+
+    pub fn sine(
+        frequency_hz: f64,
+        duration: Duration,
+        amplitude: f64,
+        sample_rate: u32,
+        bits_per_sample: u16,
+    ) -> Result<WavFile, WavError> {
+        // Mono tone, amplitude is relative to full scale (1.0 being the loudest possible)
+        let frames = (duration.as_secs_f64() * sample_rate as f64).round() as usize;
+        let channel: Vec<f64> = (0..frames)
+            .map(|i| amplitude * f64::sin(2. * PI * frequency_hz * i as f64 / sample_rate as f64))
+            .collect();
+        Self::from_normalized_channels(&[channel], sample_rate, bits_per_sample)
+    }
+
+    fn from_normalized_channels(
+        channels: &[Vec<f64>],
+        sample_rate: u32,
+        bits_per_sample: u16,
+    ) -> Result<WavFile, WavError> {
+        let samples = match channels {
+            [main_channel] => {
+                AudioSamples::from_f64_mono_normalized(main_channel, bits_per_sample, false)?
+            }
+            [left_channel, right_channel] => AudioSamples::from_f64_stereo_normalized(
+                left_channel,
+                right_channel,
+                bits_per_sample,
+                false,
+            )?,
+            _ => return Err(WavError::InvalidWAudioFormat),
+        };
+
+        Ok(Self::from_samples(samples, sample_rate))
     }
 
     // STRUCT WRITING TO FILE
 
-    fn create_le_bytes_vector(&self) -> Vec<u8> {
+    fn create_le_bytes_vector(&self) -> Result<Vec<u8>, WavError> {
         fn write_head_subchunk_to_vec(head: &WavHead, v: &mut Vec<u8>) {
             v.extend_from_slice(&head.chunk_id);
             v.extend_from_slice(&head.chunk_size.to_le_bytes());
@@ -283,12 +729,33 @@ impl WavFile {
         fn write_fmt_subchunk_to_vec(fmt: &WavFmt, v: &mut Vec<u8>) {
             v.extend_from_slice(&fmt.subchunk_id);
             v.extend_from_slice(&fmt.subchunk_size.to_le_bytes());
-            v.extend_from_slice(&fmt.audio_format.value().to_le_bytes());
+            match &fmt.extension {
+                Some(_) => v.extend_from_slice(&WAVE_FORMAT_EXTENSIBLE.to_le_bytes()),
+                None => v.extend_from_slice(&fmt.audio_format.value().to_le_bytes()),
+            }
             v.extend_from_slice(&fmt.num_channels.to_le_bytes());
             v.extend_from_slice(&fmt.sample_rate.to_le_bytes());
             v.extend_from_slice(&fmt.byte_rate.to_le_bytes());
             v.extend_from_slice(&fmt.block_align.to_le_bytes());
             v.extend_from_slice(&fmt.bits_per_sample.to_le_bytes());
+
+            // The extension is written from its fields, as they may have changed since reading
+            match &fmt.extension {
+                Some(extension) => {
+                    v.extend_from_slice(&fmt.extra[..2]);
+                    v.extend_from_slice(&extension.valid_bits_per_sample.to_le_bytes());
+                    v.extend_from_slice(&extension.channel_mask.to_le_bytes());
+                    v.extend_from_slice(&extension.sub_format);
+                    v.extend_from_slice(&fmt.extra[24..]);
+                }
+                None => v.extend_from_slice(&fmt.extra),
+            }
+        }
+
+        fn write_fact_subchunk_to_vec(fact: &WavFact, v: &mut Vec<u8>) {
+            v.extend_from_slice(&fact.subchunk_id);
+            v.extend_from_slice(&fact.subchunk_size.to_le_bytes());
+            v.extend_from_slice(&fact.sample_length.to_le_bytes());
         }
 
         fn write_data_subchunk_to_vec(data: &WavData, v: &mut Vec<u8>) {
@@ -297,83 +764,2666 @@ impl WavFile {
             v.extend(data.data.to_le_bytes_vector());
         }
 
+        // Every chunk has to start at an even offset, odd-sized ones are followed by a zero byte
+        fn write_pad_byte_to_vec(subchunk_size: u32, v: &mut Vec<u8>) {
+            if subchunk_size % 2 == 1 {
+                v.push(0);
+            }
+        }
+
+        // cbSize and anything after the extension are taken from extra, so it has to hold them
+        if self.fmt.extension.is_some() && self.fmt.extra.len() < 24 {
+            return Err(WavError::UnexpectedLength);
+        }
+
         let mut v: Vec<u8> = Vec::new();
 
         write_head_subchunk_to_vec(&self.head, &mut v);
         write_fmt_subchunk_to_vec(&self.fmt, &mut v);
+        write_pad_byte_to_vec(self.fmt.subchunk_size, &mut v);
+        if let Some(fact) = &self.fact {
+            write_fact_subchunk_to_vec(fact, &mut v);
+        }
         write_data_subchunk_to_vec(&self.data, &mut v);
+        write_pad_byte_to_vec(self.data.subchunk_size, &mut v);
+        v.extend(self.info_chunk_bytes());
 
-        v
+        Ok(v)
+    }
+
+    pub fn denoise_in_place(file_path: &str, treshold_percentage: f64) -> Result<(), WavError> {
+        // Denoises the file and writes it back under the same path
+        // The original is only renamed to file_path.bak before writing, so a crash in the middle
+        // leaves it on disk - and a file that fails to load or denoise is not touched at all
+        // An earlier backup of the same file is replaced
+        let backup_path = format!("{file_path}.bak");
+        let mut wav = Self::from_wav_file(file_path)?;
+        wav.denoise_data_fft(treshold_percentage, None)?;
+
+        fs::rename(file_path, &backup_path).map_err(WavError::IoError)?;
+        wav.save_to_file(file_path)
     }
 
     pub fn save_to_file(&self, file_path: &str) -> Result<(), WavError> {
-        let v = self.create_le_bytes_vector();
-        fs::write(file_path, &v).map_err(WavError::IoError)
+        let file = fs::File::create(file_path).map_err(WavError::IoError)?;
+        self.write_to(file)
     }
 
-    pub fn denoise_data_fft(&mut self, treshold_percentage: f64) -> Result<(), WavError> {
-        // This modifies in place
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), WavError> {
+        // Any writer - a file, stdout, or a Vec<u8> to keep the bytes in memory
+        let v = self.create_le_bytes_vector()?;
+        writer.write_all(&v).map_err(WavError::IoError)?;
+        writer.flush().map_err(WavError::IoError)
+    }
+
+    pub fn num_frames(&self) -> usize {
+        // Number of samples in every channel
+        // Encoded data cannot be counted, the fact chunk tells how many samples it decodes to
+        // (without one, the size of the data is exact for float and only a guess for the others)
+        match (&self.data.data, &self.fact) {
+            (AudioSamples::Encoded(_), Some(fact)) => fact.sample_length as usize,
+            (AudioSamples::Encoded(bytes), None) => {
+                bytes.len() / self.fmt.block_align.max(1) as usize
+            }
+            (samples, _) => samples.len(),
+        }
+    }
 
-        fn denoise_fft(samples: Vec<f64>, treshold_percentage: f64) -> Vec<f64> {
-            // Denoising below applies the low-pass-filter using FFT
-            // It naively zeros all the frequencies, whose amplitude is lesser than threshold
-            // Threshold itself is calculated as treshold_percentage * max_frequency_amplitude
+    pub fn channel(&self, index: usize) -> Result<Vec<f64>, WavError> {
+        // A single channel as f64 (0 is left for stereo)
+        self.channels_f64()?
+            .into_iter()
+            .nth(index)
+            .ok_or(WavError::InvalidChannel(index))
+    }
 
-            let original_length = samples.len();
-            let (mut re, mut im) = fft_real_zero_padded(&samples);
-            let n = re.len();
+    pub fn extract_channel(&self, index: usize) -> Result<WavFile, WavError> {
+        // A single channel as its own mono file, same sample rate and bit depth
+        let channel = self.channel(index)?;
+        let samples = AudioSamples::from_f64_mono(&channel, self.fmt.bits_per_sample, false)?;
+        Ok(WavFile::from_samples(samples, self.fmt.sample_rate))
+    }
 
-            // The samples are  padded to the nearest power of 2
-            // If we do not wish for silence at the end of new
-            // audiofile it has to be truncated after IFFT
+    // Audio data split into channels, converted to f64
+    fn channels_f64(&self) -> Result<Vec<Vec<f64>>, WavError> {
+        self.check_pcm()?;
+        if self.data.data.num_channels() == 1 {
+            Ok(vec![self.data.data.to_f64_mono()?])
+        } else {
+            let (left_channel, right_channel) = self.data.data.to_f64_stereo()?;
+            Ok(vec![left_channel, right_channel])
+        }
+    }
 
-            // Compute the magnitudes of the signal in each frequency
-            let magnitudes: Vec<f64> = re
-                .iter()
-                .zip(im.iter())
-                .map(|(re, im)| (re.powi(2) + im.powi(2)).sqrt())
-                .collect();
+    // Inverse of the above, keeps the current bit depth
+    fn set_channels_f64(&mut self, channels: &[Vec<f64>]) -> Result<(), WavError> {
+        self.data.data = match channels {
+            [main_channel] => {
+                AudioSamples::from_f64_mono(main_channel, self.fmt.bits_per_sample, false)?
+            }
+            [left_channel, right_channel] => AudioSamples::from_f64_stereo(
+                left_channel,
+                right_channel,
+                self.fmt.bits_per_sample,
+                false,
+            )?,
+            _ => return Err(WavError::InvalidWAudioFormat),
+        };
+        Ok(())
+    }
+
+    // Has to be called after the number of samples or the tags changed
+    fn update_sizes(&mut self) {
+        // Counted from the frames rather than by serializing all the samples
+        // The fact chunk of encoded data is left as it was read, it cannot be recounted
+        let num_frames = self.num_frames() as u32;
+        if self.fact.is_none() && self.fmt.audio_format != AudioFormat::Pcm {
+            self.fact = Some(new_fact(num_frames));
+        }
+        let samples = &self.data.data;
+        if let AudioSamples::Encoded(bytes) = samples {
+            self.data.subchunk_size = bytes.len() as u32;
+        } else {
+            let bytes_per_frame =
+                samples.num_channels() as usize * samples.bits_per_sample() as usize / 8;
+            self.data.subchunk_size = (samples.len() * bytes_per_frame) as u32;
+            if let Some(fact) = &mut self.fact {
+                fact.sample_length = num_frames;
+            }
+        }
+
+        // The pad bytes after odd-sized subchunks count towards the RIFF size
+        let fmt_size = self.fmt.subchunk_size + self.fmt.subchunk_size % 2;
+        let fact_size = self.fact.as_ref().map_or(0, |fact| 8 + fact.subchunk_size);
+        let data_size = self.data.subchunk_size + self.data.subchunk_size % 2;
+        let info_size = self.info_chunk_bytes().len() as u32;
+        self.head.chunk_size = 4 + (8 + fmt_size) + fact_size + (8 + data_size) + info_size;
+    }
+
+    // The whole LIST/INFO chunk, nothing at all when there are no tags
+    fn info_chunk_bytes(&self) -> Vec<u8> {
+        if self.info.is_empty() {
+            return Vec::new();
+        }
+
+        let mut tags: Vec<u8> = Vec::new();
+        for (id, text) in &self.info {
+            let text_size = text.len() as u32 + 1;
+            tags.extend_from_slice(id);
+            tags.extend_from_slice(&text_size.to_le_bytes());
+            tags.extend_from_slice(text.as_bytes());
+            tags.push(0);
+            if text_size % 2 == 1 {
+                tags.push(0);
+            }
+        }
+
+        let mut v: Vec<u8> = Vec::new();
+        v.extend_from_slice(b"LIST");
+        v.extend_from_slice(&(4 + tags.len() as u32).to_le_bytes());
+        v.extend_from_slice(b"INFO");
+        v.extend(tags);
+        v
+    }
+
+    pub fn info_tags(&self) -> Vec<(String, String)> {
+        self.info
+            .iter()
+            .map(|(id, text)| (String::from_utf8_lossy(id).to_string(), text.clone()))
+            .collect()
+    }
+
+    pub fn set_info_tag(&mut self, id: &str, value: &str) -> Result<(), WavError> {
+        // Replaces the tag if it is already there, otherwise adds it at the end
+        // Ids are four ASCII characters, e.g. "INAM" for the title or "IART" for the artist
+        let id: [u8; 4] = id
+            .as_bytes()
+            .try_into()
+            .ok()
+            .filter(|id: &[u8; 4]| id.is_ascii())
+            .ok_or_else(|| WavError::InvalidInfoTag(id.to_string()))?;
+        // The text is NUL terminated, so it cannot hold one itself
+        let value = value.replace('\0', "");
+
+        match self.info.iter_mut().find(|(tag_id, _)| *tag_id == id) {
+            Some((_, text)) => *text = value,
+            None => self.info.push((id, value)),
+        }
+        self.update_sizes();
+        Ok(())
+    }
+
+    // Only uncompressed integer samples can be denoised
+    fn check_pcm(&self) -> Result<(), WavError> {
+        if self.fmt.audio_format != AudioFormat::Pcm {
+            return Err(WavError::UnsupportedCompressedFormat(
+                self.fmt.audio_format.value(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_same_format(&self, other: &WavFile) -> Result<(), WavError> {
+        if self.fmt.sample_rate != other.fmt.sample_rate {
+            return Err(WavError::FormatMismatch(format!(
+                "sample rate {} vs {}",
+                self.fmt.sample_rate, other.fmt.sample_rate
+            )));
+        }
+        if self.fmt.num_channels != other.fmt.num_channels {
+            return Err(WavError::FormatMismatch(format!(
+                "number of channels {} vs {}",
+                self.fmt.num_channels, other.fmt.num_channels
+            )));
+        }
+        if self.fmt.bits_per_sample != other.fmt.bits_per_sample {
+            return Err(WavError::FormatMismatch(format!(
+                "bits per sample {} vs {}",
+                self.fmt.bits_per_sample, other.fmt.bits_per_sample
+            )));
+        }
+        Ok(())
+    }
 
-            // Find the greatest magnitude - it will be used to apply treshold accordingly
-            let max_magnitude = magnitudes.iter().fold(0.0_f64, |a, &b| a.max(b));
+    // Every denoising method starts with this - without any samples there is no spectrum
+    // to threshold, the FFTs would be fed nothing (or a single padded sample)
+    fn check_denoisable(&self) -> Result<(), WavError> {
+        self.check_pcm()?;
+        if self.num_frames() == 0 {
+            return Err(WavError::EmptyAudioData);
+        }
+        Ok(())
+    }
 
-            // Calculate the lower threshold to apply the low-pass-filter
-            // by zeroing frequencies below the threshold
-            let treshold = treshold_percentage * max_magnitude;
+    // Applies the given transformation to every channel separately
+    fn map_channels<F>(&mut self, f: F) -> Result<(), WavError>
+    where
+        F: Fn(Vec<f64>) -> Vec<f64>,
+    {
+        let channels: Vec<Vec<f64>> = self.channels_f64()?.into_iter().map(f).collect();
+        self.set_channels_f64(&channels)
+    }
 
-            for i in 0..n {
-                if magnitudes[i] < treshold {
-                    re[i] = 0.0;
-                    im[i] = 0.0;
+    pub fn rms(&self) -> Result<Vec<f64>, WavError> {
+        // Root mean square of every channel, relative to full scale (a full scale sine gives ~0.707)
+        let full_scale = self.data.data.full_scale();
+        Ok(self
+            .channels_f64()?
+            .iter()
+            .map(|channel| {
+                if channel.is_empty() {
+                    return 0.0;
                 }
+                let power = channel.iter().map(|x| x * x).sum::<f64>() / channel.len() as f64;
+                power.sqrt() / full_scale
+            })
+            .collect())
+    }
+
+    pub fn peak(&self) -> Result<Vec<f64>, WavError> {
+        // Largest absolute sample of every channel, relative to full scale
+        let full_scale = self.data.data.full_scale();
+        Ok(self
+            .channels_f64()?
+            .iter()
+            .map(|channel| channel.iter().fold(0.0, |peak: f64, x| peak.max(x.abs())) / full_scale)
+            .collect())
+    }
+
+    pub fn clipping_ratio(&self) -> Result<f64, WavError> {
+        // Fraction of the samples (of all channels) at 99% of full scale or above
+        // Denoising cannot bring back the peaks cut off by clipping
+        let threshold = CLIPPING_LEVEL * self.data.data.full_scale();
+        let channels = self.channels_f64()?;
+        let total: usize = channels.iter().map(|channel| channel.len()).sum();
+        if total == 0 {
+            return Ok(0.0);
+        }
+
+        let clipped = channels
+            .iter()
+            .flatten()
+            .filter(|sample| sample.abs() >= threshold)
+            .count();
+        Ok(clipped as f64 / total as f64)
+    }
+
+    pub fn metadata_json(&self) -> Result<String, WavError> {
+        // Format and levels of the file as JSON, for tooling and debugging
+        let num_frames = self.num_frames();
+        let metadata = WavMetadata {
+            head: &self.head,
+            fmt: &self.fmt,
+            fact: self.fact.as_ref(),
+            data: &self.data,
+            duration_secs: num_frames as f64 / self.fmt.sample_rate.max(1) as f64,
+            num_frames,
+            peak: self.peak()?,
+            rms: self.rms()?,
+        };
+        serde_json::to_string_pretty(&metadata).map_err(|e| WavError::IoError(e.into()))
+    }
+
+    pub fn export_samples_csv(
+        &self,
+        file_path: &str,
+        max_samples: Option<usize>,
+    ) -> Result<(), WavError> {
+        // Samples relative to full scale, one frame per row - for plotting in a spreadsheet
+        // or with external tools, max_samples keeps only the first ones of a long file
+        let full_scale = self.data.data.full_scale();
+        let channels = self.channels_f64()?;
+        let frames = max_samples.map_or(channels[0].len(), |max| max.min(channels[0].len()));
+
+        let header = match channels.len() {
+            1 => "index,value",
+            _ => "index,left,right",
+        };
+
+        let file = fs::File::create(file_path).map_err(WavError::IoError)?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "{header}").map_err(WavError::IoError)?;
+        for i in 0..frames {
+            write!(writer, "{i}").map_err(WavError::IoError)?;
+            for channel in &channels {
+                write!(writer, ",{}", channel[i] / full_scale).map_err(WavError::IoError)?;
+            }
+            writeln!(writer).map_err(WavError::IoError)?;
+        }
+        writer.flush().map_err(WavError::IoError)
+    }
+
+    fn downmix(&self) -> Result<Vec<f64>, WavError> {
+        // Mean of the channels at every frame
+        let channels = self.channels_f64()?;
+        Ok((0..channels[0].len())
+            .map(|i| channels.iter().map(|channel| channel[i]).sum::<f64>() / channels.len() as f64)
+            .collect())
+    }
+
+    pub fn dominant_frequency(&self) -> Result<f64, WavError> {
+        // Frequency of the loudest bin of the (downmixed to mono) spectrum, DC is skipped
+        // Accurate to one bin, i.e. sample_rate / padded length
+        let mono = self.downmix()?;
+        if mono.is_empty() {
+            return Err(WavError::EmptyAudioData);
+        }
+
+        let (re, im) = fft_real_zero_padded(&mono);
+        let n = re.len();
+        let peak_bin = (1..=n / 2)
+            .max_by(|&a, &b| {
+                let magnitude_a = re[a].powi(2) + im[a].powi(2);
+                let magnitude_b = re[b].powi(2) + im[b].powi(2);
+                magnitude_a.total_cmp(&magnitude_b)
+            })
+            .unwrap_or(0);
+        Ok(bin_to_hz(peak_bin, n, self.fmt.sample_rate))
+    }
+
+    pub fn spectrogram(&self, window_size: usize, hop: usize) -> Result<Vec<Vec<f64>>, WavError> {
+        // dB magnitudes of the (downmixed to mono) signal, one row per frame, one column per bin
+        // from DC up to Nyquist, relative to full scale, see denoise::spectrogram
+        // Bin k of a row is at fft::bin_to_hz(k, window_size.next_power_of_two(), sample_rate)
+        let full_scale = self.data.data.full_scale();
+        let mono: Vec<f64> = self.downmix()?.iter().map(|x| x / full_scale).collect();
+        Ok(spectrogram(&mono, window_size, hop))
+    }
+
+    pub fn apply_fade(&mut self, fade_in: Duration, fade_out: Duration) -> Result<(), WavError> {
+        // Linear ramps at both ends of the file, used to get rid of the clicks
+        // introduced by zero padding and truncation during denoising
+
+        let sample_rate = self.fmt.sample_rate as f64;
+        let fade_in_length = (fade_in.as_secs_f64() * sample_rate).round() as usize;
+        let fade_out_length = (fade_out.as_secs_f64() * sample_rate).round() as usize;
+
+        self.map_channels(|mut samples| {
+            // If the file is shorter than the fades, they are clamped to its length
+            // Overlapping parts get both gains applied
+            let n = samples.len();
+            let fade_in_length = fade_in_length.min(n);
+            let fade_out_length = fade_out_length.min(n);
+
+            for (i, sample) in samples.iter_mut().take(fade_in_length).enumerate() {
+                *sample *= i as f64 / fade_in_length as f64;
+            }
+            for (i, sample) in samples.iter_mut().rev().take(fade_out_length).enumerate() {
+                *sample *= i as f64 / fade_out_length as f64;
+            }
+
+            samples
+        })
+    }
+
+    pub fn trim_silence(&mut self, threshold_ratio: f64) -> Result<(), WavError> {
+        // Cuts off everything before the first and after the last sample
+        // louder than threshold_ratio * full_scale
+
+        let threshold = threshold_ratio * self.data.data.full_scale();
+        let channels = self.channels_f64()?;
+        let frames = channels[0].len();
+
+        // For stereo the louder channel decides for the whole frame
+        let is_loud = |i: usize| channels.iter().any(|channel| channel[i].abs() > threshold);
+
+        let trimmed: Vec<Vec<f64>> = match (0..frames).find(|&i| is_loud(i)) {
+            Some(start) => {
+                let end = (0..frames).rfind(|&i| is_loud(i)).unwrap_or(start) + 1;
+                channels.iter().map(|c| c[start..end].to_vec()).collect()
+            }
+            // Nothing but silence
+            None => channels.iter().map(|_| Vec::new()).collect(),
+        };
+
+        self.set_channels_f64(&trimmed)?;
+        self.update_sizes();
+        Ok(())
+    }
+
+    pub fn slice(&self, start: Duration, end: Duration) -> Result<WavFile, WavError> {
+        // Copy of the fragment between start and end, the end is clamped to the file length
+        let sample_rate = self.fmt.sample_rate as f64;
+        let channels = self.channels_f64()?;
+        let frames = channels[0].len();
+
+        let start_index = (start.as_secs_f64() * sample_rate).round() as usize;
+        let end_index = ((end.as_secs_f64() * sample_rate).round() as usize).min(frames);
+        if start_index >= end_index {
+            return Err(WavError::InvalidTimeRange);
+        }
+
+        let sliced: Vec<Vec<f64>> = channels
+            .iter()
+            .map(|channel| channel[start_index..end_index].to_vec())
+            .collect();
+
+        let mut wav = self.clone();
+        wav.set_channels_f64(&sliced)?;
+        wav.update_sizes();
+        Ok(wav)
+    }
+
+    pub fn set_duration(&mut self, target: Duration) -> Result<(), WavError> {
+        // Cuts the end off or appends silence, until the file is exactly target long
+        // (to the nearest frame at the current sample rate)
+        let frames = (target.as_secs_f64() * self.fmt.sample_rate as f64).round() as usize;
+        let mut channels = self.channels_f64()?;
+        for channel in channels.iter_mut() {
+            channel.resize(frames, 0.0);
+        }
+
+        self.set_channels_f64(&channels)?;
+        self.update_sizes();
+        Ok(())
+    }
+
+    pub fn pad_samples(&mut self, front: usize, back: usize) -> Result<(), WavError> {
+        // Adds front frames of silence before the audio and back frames after it
+        // Exact counts, e.g. for lining the file up with an FFT length
+        let channels: Vec<Vec<f64>> = self
+            .channels_f64()?
+            .iter()
+            .map(|channel| {
+                let mut padded = vec![0.0; front];
+                padded.extend_from_slice(channel);
+                padded.resize(front + channel.len() + back, 0.0);
+                padded
+            })
+            .collect();
+
+        self.set_channels_f64(&channels)?;
+        self.update_sizes();
+        Ok(())
+    }
+
+    pub fn reverse(&mut self) -> Result<(), WavError> {
+        self.check_pcm()?;
+        self.data.data.reverse();
+        Ok(())
+    }
+
+    pub fn set_channels(&mut self, num_channels: u16) -> Result<(), WavError> {
+        // Mono to stereo copies the channel to both sides, stereo to mono takes their mean
+        // Only these two are handled, like everywhere else in the file
+        let channels = match (self.fmt.num_channels, num_channels) {
+            (current, new) if current == new => return Ok(()),
+            (1, 2) => {
+                let channel = self.downmix()?;
+                vec![channel.clone(), channel]
             }
+            (2, 1) => vec![self.downmix()?],
+            _ => return Err(WavError::UnsupportedChannelCount(num_channels)),
+        };
+
+        self.fmt.num_channels = num_channels;
+        self.fmt.block_align = num_channels * self.fmt.bits_per_sample / 8;
+        self.fmt.byte_rate = self.fmt.sample_rate * self.fmt.block_align as u32;
+        if let Some(extension) = &mut self.fmt.extension {
+            // Front center for mono, front left and right for stereo
+            extension.channel_mask = if num_channels == 1 { 0x4 } else { 0x3 };
+        }
+
+        self.set_channels_f64(&channels)?;
+        self.update_sizes();
+        Ok(())
+    }
+
+    pub fn invert_polarity(&mut self, channel: Option<usize>) -> Result<(), WavError> {
+        // Flips the sign of one channel (0 is left for stereo), or of all of them for None
+        let num_channels = self.fmt.num_channels as usize;
+        if let Some(index) = channel.filter(|&index| index >= num_channels) {
+            return Err(WavError::InvalidChannel(index));
+        }
+        self.check_pcm()?;
+        self.data.data.invert_polarity(channel);
+        Ok(())
+    }
+
+    pub fn remove_dc_offset(&mut self) -> Result<(), WavError> {
+        // Subtracts the mean of every channel, so the waveform is centered around 0 again
+        // A constant offset is inaudible, but it takes away headroom and clicks when cut
+        // Values out of range saturate at the integer limits when converted back
+        self.map_channels(|channel| {
+            let mean = channel.iter().sum::<f64>() / channel.len().max(1) as f64;
+            channel.iter().map(|x| x - mean).collect()
+        })
+    }
+
+    pub fn noise_gate(
+        &mut self,
+        threshold_ratio: f64,
+        attack: Duration,
+        release: Duration,
+    ) -> Result<(), WavError> {
+        // Silences everything quieter than threshold_ratio * full_scale, e.g. the gaps between words
+        // The gain opens over the attack time and closes over the release time, so there are no clicks
+        // For stereo the louder channel decides for both
 
-            // Truncate IFFT output
-            let (re_denoised, _) = ifft(&re, &im);
-            let output = re_denoised[..original_length].to_vec();
+        let threshold = threshold_ratio * self.data.data.full_scale();
+        let sample_rate = self.fmt.sample_rate as f64;
+        let attack_length = (attack.as_secs_f64() * sample_rate).max(1.0);
+        let release_length = (release.as_secs_f64() * sample_rate).max(1.0);
 
-            output
+        let mut channels = self.channels_f64()?;
+        let frames = channels[0].len();
+
+        // The envelope follows the peaks and decays over the release time,
+        // otherwise the gate would start closing at every zero crossing
+        let decay = f64::exp(-1.0 / release_length);
+        let mut envelope: f64 = 0.0;
+        let mut gain: f64 = 0.0;
+
+        for i in 0..frames {
+            let level = channels.iter().fold(0.0, |a: f64, c| a.max(c[i].abs()));
+            envelope = level.max(envelope * decay);
+
+            gain = if envelope > threshold {
+                (gain + 1.0 / attack_length).min(1.0)
+            } else {
+                (gain - 1.0 / release_length).max(0.0)
+            };
+
+            for channel in channels.iter_mut() {
+                channel[i] *= gain;
+            }
         }
 
-        match self.data.data {
-            AudioSamples::MonoI8(_) | AudioSamples::MonoI16(_) | AudioSamples::MonoI32(_) => {
-                let main_channel = self.data.data.to_f64_mono()?;
-                let denoised_samples = denoise_fft(main_channel, treshold_percentage);
-                self.data.data =
-                    AudioSamples::from_f64_mono(&denoised_samples, self.fmt.bits_per_sample)?;
-                Ok(())
+        self.set_channels_f64(&channels)
+    }
+
+    pub fn limit(&mut self, ceiling_ratio: f64) -> Result<(), WavError> {
+        // Keeps the peaks below ceiling_ratio * full_scale, e.g. after mix pushed them into clipping
+        // Unlike clamping, the gain goes down smoothly over the look-ahead time before a peak
+        // and comes back up over the release time, so the waveform keeps its shape
+        // For stereo the louder channel decides for both
+
+        let ceiling = ceiling_ratio.max(0.0) * self.data.data.full_scale();
+        let mut channels = self.channels_f64()?;
+        self.limit_channels(&mut channels, ceiling);
+        self.set_channels_f64(&channels)
+    }
+
+    // The limiter itself, on samples not yet written back (so they can still exceed full scale)
+    fn limit_channels(&self, channels: &mut [Vec<f64>], ceiling: f64) {
+        let sample_rate = self.fmt.sample_rate as f64;
+        let lookahead = (LIMITER_LOOKAHEAD.as_secs_f64() * sample_rate).max(1.0) as usize;
+        let release_length = (LIMITER_RELEASE.as_secs_f64() * sample_rate).max(1.0);
+        let frames = channels[0].len();
+
+        // Gain each frame needs on its own to stay under the ceiling
+        let required: Vec<f64> = (0..frames)
+            .map(|i| {
+                let level = channels.iter().fold(0.0, |a: f64, c| a.max(c[i].abs()));
+                if level > ceiling {
+                    ceiling / level
+                } else {
+                    1.0
+                }
+            })
+            .collect();
+
+        // The smallest gain needed anywhere in the next `lookahead` frames,
+        // recovering towards 1 over the release time afterwards
+        let mut released: f64 = 1.0;
+        let envelope: Vec<f64> = sliding_minimum(&required, 0, lookahead)
+            .iter()
+            .map(|&minimum| {
+                released = minimum.min(released + (1.0 - released) / release_length);
+                released
+            })
+            .collect();
+
+        // Averaging over the last `lookahead` frames turns every drop into a ramp
+        // Each of the averaged values already looked far enough ahead to include frame i,
+        // so the averaged gain is never more than the frame needs
+        let mut sum: f64 = 0.0;
+        for i in 0..frames {
+            sum += envelope[i];
+            if i > lookahead {
+                sum -= envelope[i - lookahead - 1];
             }
-            AudioSamples::StereoI8(_) | AudioSamples::StereoI16(_) | AudioSamples::StereoI32(_) => {
-                let (left_channel, right_channel) = self.data.data.to_f64_stereo()?;
-                let denoised_left = denoise_fft(left_channel, treshold_percentage);
-                let denoised_right = denoise_fft(right_channel, treshold_percentage);
-                self.data.data = AudioSamples::from_f64_stereo(
-                    &denoised_left,
-                    &denoised_right,
-                    self.fmt.bits_per_sample,
-                )?;
-                Ok(())
+            let gain = sum / (i.min(lookahead) + 1) as f64;
+
+            for channel in channels.iter_mut() {
+                channel[i] *= gain;
             }
         }
     }
+
+    pub fn normalize_to_rms(&mut self, target_rms_ratio: f64) -> Result<(), WavError> {
+        // Scales the file so that its RMS (of all channels together) is target_rms_ratio * full_scale,
+        // like rms() would report it - e.g. for bringing a whole library to the same loudness
+        // The peaks pushed over full scale by the gain are limited instead of clipped,
+        // which takes the RMS slightly below the target for files with loud peaks
+        // Silent files are left as they are, no gain would make them louder
+        let full_scale = self.data.data.full_scale();
+        let mut channels = self.channels_f64()?;
+
+        let samples = channels.iter().map(Vec::len).sum::<usize>().max(1);
+        let power = channels.iter().flatten().map(|x| x * x).sum::<f64>() / samples as f64;
+        if power == 0.0 {
+            return Ok(());
+        }
+        let gain = target_rms_ratio.max(0.0) * full_scale / power.sqrt();
+
+        for channel in channels.iter_mut() {
+            channel.iter_mut().for_each(|x| *x *= gain);
+        }
+        self.limit_channels(&mut channels, NORMALIZE_CEILING * full_scale);
+        self.set_channels_f64(&channels)
+    }
+
+    pub fn append(&mut self, other: &WavFile) -> Result<(), WavError> {
+        // Joins the other file's samples at the end of this one
+        self.check_same_format(other)?;
+        self.check_pcm()?;
+        other.check_pcm()?;
+        self.data.data.append(&other.data.data)?;
+        self.update_sizes();
+        Ok(())
+    }
+
+    pub fn mix(&mut self, other: &WavFile, other_gain: f64) -> Result<(), WavError> {
+        // Adds the other file's samples, scaled by other_gain, on top of this one
+        // The shorter file is treated as if it was followed by silence
+        self.check_same_format(other)?;
+
+        let channels = self.channels_f64()?;
+        let other_channels = other.channels_f64()?;
+
+        let mixed: Vec<Vec<f64>> = channels
+            .iter()
+            .zip(other_channels.iter())
+            .map(|(channel, other_channel)| {
+                let length = channel.len().max(other_channel.len());
+                (0..length)
+                    .map(|i| {
+                        channel.get(i).unwrap_or(&0.0)
+                            + other_gain * other_channel.get(i).unwrap_or(&0.0)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Values out of range saturate at the integer limits when converted back
+        self.set_channels_f64(&mixed)?;
+        self.update_sizes();
+        Ok(())
+    }
+
+    pub fn difference(&self, other: &WavFile) -> Result<WavFile, WavError> {
+        // self - other, sample by sample - for the original minus the denoised file
+        // that is whatever the denoising removed
+        self.check_same_format(other)?;
+        if self.num_frames() != other.num_frames() {
+            return Err(WavError::FormatMismatch(format!(
+                "length {} vs {}",
+                self.num_frames(),
+                other.num_frames()
+            )));
+        }
+
+        let difference: Vec<Vec<f64>> = self
+            .channels_f64()?
+            .iter()
+            .zip(other.channels_f64()?.iter())
+            .map(|(channel, other_channel)| {
+                channel
+                    .iter()
+                    .zip(other_channel.iter())
+                    .map(|(a, b)| a - b)
+                    .collect()
+            })
+            .collect();
+
+        // Values out of range saturate at the integer limits when converted back
+        let mut wav = self.clone();
+        wav.set_channels_f64(&difference)?;
+        Ok(wav)
+    }
+
+    pub fn downsample_filtered(&mut self, new_rate: u32) -> Result<(), WavError> {
+        // Dropping samples alone would fold everything above the new Nyquist frequency
+        // back into the audible band, so it is filtered out first
+
+        let old_rate = self.fmt.sample_rate;
+        if new_rate == 0 || new_rate >= old_rate {
+            return Err(WavError::InvalidSampleRate(new_rate));
+        }
+        let ratio = old_rate as f64 / new_rate as f64;
+
+        self.map_channels(|channel| {
+            let filtered = low_pass_filter(&channel, new_rate as f64 / 2.0, old_rate);
+            let new_length = (filtered.len() as f64 / ratio) as usize;
+
+            // Linear interpolation between the neighbouring samples,
+            // for integer ratios it simply picks every ratio-th sample
+            (0..new_length)
+                .map(|i| {
+                    let position = i as f64 * ratio;
+                    let index = position as usize;
+                    let fraction = position - index as f64;
+                    let next = filtered.get(index + 1).copied().unwrap_or(filtered[index]);
+                    filtered[index] * (1.0 - fraction) + next * fraction
+                })
+                .collect()
+        })?;
+
+        self.fmt.sample_rate = new_rate;
+        self.fmt.byte_rate = new_rate * self.fmt.block_align as u32;
+        self.update_sizes();
+        Ok(())
+    }
+
+    pub fn convert_bit_depth(&mut self, new_bits: u16, dither: bool) -> Result<(), WavError> {
+        // Samples are rescaled between the full scale ranges,
+        // e.g. 16 -> 32 bits multiplies by 65536, which can be undone without any loss
+        // Dithering is only worth it when going down, see AudioSamples::tpdf_dither
+        let full_scale = self.data.data.full_scale();
+        let channels: Vec<Vec<f64>> = self
+            .channels_f64()?
+            .iter()
+            .map(|channel| channel.iter().map(|&b| b / full_scale).collect())
+            .collect();
+
+        self.data.data = match channels.as_slice() {
+            [main_channel] => {
+                AudioSamples::from_f64_mono_normalized(main_channel, new_bits, dither)?
+            }
+            [left_channel, right_channel] => AudioSamples::from_f64_stereo_normalized(
+                left_channel,
+                right_channel,
+                new_bits,
+                dither,
+            )?,
+            _ => return Err(WavError::InvalidWAudioFormat),
+        };
+
+        self.fmt.bits_per_sample = new_bits;
+        self.fmt.block_align = self.fmt.num_channels * new_bits / 8;
+        self.fmt.byte_rate = self.fmt.sample_rate * self.fmt.block_align as u32;
+        if let Some(extension) = &mut self.fmt.extension {
+            extension.valid_bits_per_sample = new_bits;
+        }
+        self.update_sizes();
+        Ok(())
+    }
+
+    pub fn filtfilt(&mut self, coeffs: &BiquadCoeffs) -> Result<(), WavError> {
+        // Time domain filtering without phase distortion, see biquad::filtfilt
+        self.check_denoisable()?;
+        self.map_channels(|channel| filtfilt(&channel, coeffs))
+    }
+
+    pub fn remove_hum(
+        &mut self,
+        fundamental_hz: f64,
+        num_harmonics: usize,
+        bandwidth_hz: f64,
+    ) -> Result<(), WavError> {
+        // Mains hum, usually 50 Hz in Europe and 60 Hz in the US
+        self.check_denoisable()?;
+        let sample_rate = self.fmt.sample_rate;
+        self.map_channels(|channel| {
+            notch_filter(
+                &channel,
+                fundamental_hz,
+                num_harmonics,
+                bandwidth_hz,
+                sample_rate,
+            )
+        })
+    }
+
+    pub fn estimate_noise_psd(&self, start: Duration, end: Duration) -> Result<Vec<f64>, WavError> {
+        // The fragment between start and end should contain nothing but noise (e.g. a pause)
+        // For stereo the PSDs of both channels are averaged
+
+        let sample_rate = self.fmt.sample_rate as f64;
+        let channels = self.channels_f64()?;
+        let frames = channels[0].len();
+
+        let start_index = (start.as_secs_f64() * sample_rate).round() as usize;
+        let end_index = ((end.as_secs_f64() * sample_rate).round() as usize).min(frames);
+        if start_index >= end_index {
+            return Err(WavError::InvalidTimeRange);
+        }
+
+        let psds: Vec<Vec<f64>> = channels
+            .iter()
+            .map(|channel| noise_psd(&channel[start_index..end_index]))
+            .collect();
+
+        let n = psds[0].len();
+        Ok((0..n)
+            .map(|k| psds.iter().map(|psd| psd[k]).sum::<f64>() / psds.len() as f64)
+            .collect())
+    }
+
+    pub fn adaptive_noise_floor(&self, window: Duration) -> Result<Vec<f64>, WavError> {
+        // Noise level at every frame, relative to full scale (same units as rms)
+        // Unlike estimate_noise_psd it needs no noise-only fragment and follows a changing floor
+        // https://en.wikipedia.org/wiki/Minimum_statistics (Martin, 2001), in its simplest form:
+        // the smoothed power dips down to the noise between words and notes, so the minimum
+        // of the last `window` is taken as the noise power at the moment
+        // The window has to be longer than the longest stretch without such a pause
+
+        let sample_rate = self.fmt.sample_rate as f64;
+        let window_length = (window.as_secs_f64() * sample_rate).round() as usize;
+        let smoothing_length = (NOISE_FLOOR_SMOOTHING.as_secs_f64() * sample_rate).max(1.0);
+
+        let channels = self.channels_f64()?;
+        let frames = channels[0].len();
+
+        // Envelope follower on the power, averaged over the channels
+        let power: Vec<f64> = (0..frames)
+            .map(|i| channels.iter().map(|c| c[i] * c[i]).sum::<f64>() / channels.len() as f64)
+            .collect();
+
+        // Starts from the mean of the first smoothing time, starting from silence
+        // or a single sample would make a false minimum at the beginning
+        let decay = f64::exp(-1.0 / smoothing_length);
+        let start = &power[..(smoothing_length as usize).min(frames)];
+        let mut envelope = start.iter().sum::<f64>() / start.len().max(1) as f64;
+        let smoothed: Vec<f64> = power
+            .iter()
+            .map(|p| {
+                envelope = decay * envelope + (1.0 - decay) * p;
+                envelope
+            })
+            .collect();
+
+        let full_scale = self.data.data.full_scale();
+        Ok(sliding_minimum(&smoothed, window_length, 0)
+            .iter()
+            .map(|power| power.sqrt() / full_scale)
+            .collect())
+    }
+
+    pub fn denoise_wiener(&mut self, noise_psd: &[f64]) -> Result<(), WavError> {
+        self.check_denoisable()?;
+        self.map_channels(|channel| wiener_filter(&channel, noise_psd))
+    }
+
+    pub fn denoise_moving_average(&mut self, window: usize) -> Result<(), WavError> {
+        // Time domain smoothing, a crude low-pass filter
+        self.check_denoisable()?;
+        self.map_channels(|channel| moving_average(&channel, window))
+    }
+
+    pub fn denoise_median(&mut self, window: usize) -> Result<(), WavError> {
+        // Time domain, removes impulsive noise (clicks shorter than half the window)
+        self.check_denoisable()?;
+        self.map_channels(|channel| median_filter(&channel, window))
+    }
+
+    pub fn declick(&mut self, sensitivity: f64) -> Result<(), WavError> {
+        // Removes short clicks and pops, see denoise::declick
+        self.check_denoisable()?;
+        self.map_channels(|channel| declick(&channel, sensitivity))
+    }
+
+    pub fn denoise_data_fft(
+        &mut self,
+        treshold_percentage: f64,
+        window: Option<WindowKind>,
+    ) -> Result<(), WavError> {
+        // With a window the whole signal is tapered before the FFT, see denoise_signal_windowed
+        match window {
+            None => self
+                .denoise_data(treshold_percentage, FftLength::Truncated)
+                .map(|_| ()),
+            Some(window) => {
+                self.check_denoisable()?;
+                self.map_channels(|channel| {
+                    denoise_signal_windowed(&channel, treshold_percentage, window)
+                })
+            }
+        }
+    }
+
+    pub fn denoise_data_fft_exact(&mut self, treshold_percentage: f64) -> Result<(), WavError> {
+        // Same as above, but the FFT is done on the exact length of the signal (Bluestein)
+        // Slower, though the frequency resolution is not altered by padding
+        self.denoise_data(treshold_percentage, FftLength::Exact)
+            .map(|_| ())
+    }
+
+    pub fn denoise_data_fft_padded(&mut self, treshold_percentage: f64) -> Result<(), WavError> {
+        // Keeps the whole padded signal, so the file gets longer (up to the next power of 2)
+        // See FftLength for how padding affects the result
+        self.denoise_data(treshold_percentage, FftLength::Padded)?;
+        self.update_sizes();
+        Ok(())
+    }
+
+    pub fn denoise_data_fft_sized(
+        &mut self,
+        treshold_percentage: f64,
+        fft_size: Option<usize>,
+    ) -> Result<(), WavError> {
+        // Without a size the whole signal goes through a single FFT, padded to the next power of 2
+        // With one it is denoised in overlapping blocks of fft_size (see denoise::stft_denoise) -
+        // smaller blocks follow the changes in time better, larger ones tell frequencies apart
+        // better, the bins are sample_rate / fft_size apart
+        let Some(fft_size) = fft_size else {
+            return self.denoise_data_fft(treshold_percentage, None);
+        };
+        if fft_size < 2 || !fft_size.is_power_of_two() {
+            return Err(WavError::InvalidFftSize(fft_size));
+        }
+        self.check_denoisable()?;
+        self.map_channels(|channel| stft_denoise(&channel, fft_size, treshold_percentage))
+    }
+
+    pub fn denoise_data_fft_smooth(
+        &mut self,
+        treshold_percentage: f64,
+        smoothing_bins: usize,
+    ) -> Result<(), WavError> {
+        // Soft gain instead of zeroing, smoothed across frequency, see denoise::smooth_spectral_gate
+        self.check_denoisable()?;
+        self.map_channels(|channel| {
+            smooth_spectral_gate(&channel, treshold_percentage, smoothing_bins)
+        })
+    }
+
+    pub fn denoise_data_fft_weighted(
+        &mut self,
+        treshold_percentage: f64,
+        perceptual: bool,
+    ) -> Result<(), WavError> {
+        // With perceptual set the threshold is compared against A-weighted magnitudes,
+        // see denoise::denoise_signal_weighted - otherwise the same as denoise_data_fft
+        self.check_denoisable()?;
+        let sample_rate = self.fmt.sample_rate;
+        self.map_channels(|channel| {
+            denoise_signal_weighted(&channel, treshold_percentage, sample_rate, perceptual)
+        })
+    }
+
+    pub fn denoise_data_fft_phase_mask(
+        &mut self,
+        treshold_percentage: f64,
+        seed: u64,
+    ) -> Result<(), WavError> {
+        // Bins below the threshold keep a little of their magnitude with a random phase,
+        // see denoise::phase_mask - the right channel gets different phases, like with dither
+        self.check_denoisable()?;
+        let channels: Vec<Vec<f64>> = self
+            .channels_f64()?
+            .iter()
+            .enumerate()
+            .map(|(c, channel)| {
+                let seed = if c == 0 { seed } else { !seed };
+                phase_mask(channel, treshold_percentage, seed)
+            })
+            .collect();
+        self.set_channels_f64(&channels)
+    }
+
+    pub fn denoise_data_fft_mix(
+        &mut self,
+        treshold_percentage: f64,
+        wet: f64,
+    ) -> Result<(), WavError> {
+        // Blends the denoised signal with the original one, per sample:
+        // output = wet * denoised + (1 - wet) * original
+        self.check_denoisable()?;
+        let wet = wet.clamp(0.0, 1.0);
+        if wet == 0.0 {
+            return Ok(());
+        }
+
+        let original = self.channels_f64()?;
+        self.denoise_data_fft(treshold_percentage, None)?;
+
+        let mixed: Vec<Vec<f64>> = self
+            .channels_f64()?
+            .iter()
+            .zip(original.iter())
+            .map(|(denoised, original)| {
+                denoised
+                    .iter()
+                    .zip(original.iter())
+                    .map(|(&d, &o)| wet * d + (1.0 - wet) * o)
+                    .collect()
+            })
+            .collect();
+
+        self.set_channels_f64(&mixed)
+    }
+
+    pub fn denoise_data_fft_midside(&mut self, treshold_percentage: f64) -> Result<(), WavError> {
+        // Stereo is denoised as mid (L+R)/2 and side (L-R)/2 instead of left and right,
+        // which keeps the stereo image better - centered sounds end up in mid only
+        // Mono files are denoised as usual
+        self.check_denoisable()?;
+
+        let channels = self.channels_f64()?;
+        let [left, right] = channels.as_slice() else {
+            return self.denoise_data_fft(treshold_percentage, None);
+        };
+
+        let (mid, side): (Vec<f64>, Vec<f64>) = left
+            .iter()
+            .zip(right.iter())
+            .map(|(l, r)| ((l + r) / 2.0, (l - r) / 2.0))
+            .unzip();
+
+        let mid = denoise_signal(&mid, treshold_percentage);
+        let side = denoise_signal(&side, treshold_percentage);
+
+        let (left, right): (Vec<f64>, Vec<f64>) = mid
+            .iter()
+            .zip(side.iter())
+            .map(|(m, s)| (m + s, m - s))
+            .unzip();
+        self.set_channels_f64(&[left, right])
+    }
+
+    pub fn denoise_data_fft_channels(
+        &mut self,
+        treshold_percentage: f64,
+        channels: &[usize],
+    ) -> Result<(), WavError> {
+        // Denoises only the listed channels (0 is left for stereo), the rest is left untouched
+        // Useful when the noise is in one channel only
+        let mut all_channels = self.channels_f64()?;
+        if let Some(&index) = channels.iter().find(|&&index| index >= all_channels.len()) {
+            return Err(WavError::InvalidChannel(index));
+        }
+        self.check_denoisable()?;
+
+        for (index, channel) in all_channels.iter_mut().enumerate() {
+            if channels.contains(&index) {
+                *channel = denoise_signal(channel, treshold_percentage);
+            }
+        }
+
+        self.set_channels_f64(&all_channels)
+    }
+
+    pub fn denoise_data_fft_report(
+        &mut self,
+        treshold_percentage: f64,
+    ) -> Result<DenoiseReport, WavError> {
+        // Same as denoise_data_fft, but keeps the spectra for visualization
+        self.denoise_data(treshold_percentage, FftLength::Truncated)
+    }
+
+    fn denoise_data(
+        &mut self,
+        treshold_percentage: f64,
+        length: FftLength,
+    ) -> Result<DenoiseReport, WavError> {
+        // This modifies in place
+        self.check_denoisable()?;
+        let (denoised, spectra): (Vec<Vec<f64>>, Vec<ChannelSpectrum>) = self
+            .channels_f64()?
+            .iter()
+            .map(|channel| denoise_signal_with_spectrum(channel, treshold_percentage, length))
+            .unzip();
+
+        self.set_channels_f64(&denoised)?;
+        Ok(DenoiseReport { channels: spectra })
+    }
+}
+
+pub fn snr_db(reference: &WavFile, test: &WavFile) -> Result<f64, WavError> {
+    // Signal to noise ratio, where reference is the clean signal
+    // and whatever test differs by is considered noise
+    // Identical files give infinity (silent ones included), a silent reference with
+    // any noise at all gives negative infinity - empty files have no ratio and are an error
+    reference.check_same_format(test)?;
+
+    let reference_channels = reference.channels_f64()?;
+    let test_channels = test.channels_f64()?;
+    if reference_channels[0].len() != test_channels[0].len() {
+        return Err(WavError::FormatMismatch(format!(
+            "length {} vs {}",
+            reference_channels[0].len(),
+            test_channels[0].len()
+        )));
+    }
+    if reference_channels[0].is_empty() {
+        return Err(WavError::EmptyAudioData);
+    }
+
+    let mut signal_power = 0.0;
+    let mut noise_power = 0.0;
+    for (reference_channel, test_channel) in reference_channels.iter().zip(test_channels.iter()) {
+        for (r, t) in reference_channel.iter().zip(test_channel.iter()) {
+            signal_power += r * r;
+            noise_power += (t - r) * (t - r);
+        }
+    }
+
+    if noise_power == 0.0 {
+        return Ok(f64::INFINITY);
+    }
+    Ok(10.0 * (signal_power / noise_power).log10())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::models::fft::fft_real_zero_padded;
+    use std::f64::consts::PI;
+
+    pub(crate) fn assert_round_trip_bytes(name: &str, original: &[u8]) {
+        // Loading and saving an unmodified file has to give back the very same bytes
+        // Only holds for files without chunks the reader drops (JUNK, PAD, non-INFO lists...)
+        let wav =
+            WavFile::parse_wav_bytes(original, true).unwrap_or_else(|e| panic!("{name}: {e}"));
+
+        let mut saved: Vec<u8> = Vec::new();
+        wav.write_to(&mut saved)
+            .unwrap_or_else(|e| panic!("{name}: {e}"));
+
+        if let Some(i) = (0..original.len().min(saved.len())).find(|&i| original[i] != saved[i]) {
+            panic!("{name}: first difference at byte {i}");
+        }
+        assert_eq!(original.len(), saved.len(), "{name}: lengths differ");
+    }
+
+    pub(crate) fn assert_round_trip(path: &str) {
+        let original = fs::read(path).unwrap_or_else(|e| panic!("{path}: {e}"));
+        assert_round_trip_bytes(path, &original);
+    }
+
+    pub(crate) fn assert_round_trip_corpus(dir: &str) -> usize {
+        // Regression corpus - every .wav file directly inside the directory has to round trip
+        // Returns how many files were checked, so an empty directory is easy to spot
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .unwrap_or_else(|e| panic!("{dir}: {e}"))
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+            })
+            .collect();
+        paths.sort();
+
+        for path in &paths {
+            assert_round_trip(&path.to_string_lossy());
+        }
+        paths.len()
+    }
+
+    fn test_tone(num_channels: usize, bits_per_sample: u16) -> WavFile {
+        // A quarter of a second of two tones, different in every channel
+        let channels: Vec<Vec<f64>> = (0..num_channels)
+            .map(|c| {
+                (0..2000)
+                    .map(|i| {
+                        let t = i as f64 / 8000.0;
+                        0.5 * f64::sin(2. * PI * 440.0 * (c + 1) as f64 * t)
+                            + 0.3 * f64::sin(2. * PI * 1234.0 * t)
+                    })
+                    .collect()
+            })
+            .collect();
+        WavFile::from_normalized_channels(&channels, 8000, bits_per_sample).unwrap()
+    }
+
+    fn to_bytes(wav: &WavFile) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        wav.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn mono(channel: &[f64]) -> WavFile {
+        // 16-bit at 8 kHz, samples relative to full scale
+        WavFile::from_normalized_channels(&[channel.to_vec()], 8000, 16).unwrap()
+    }
+
+    fn noise(length: usize, amplitude: f64) -> Vec<f64> {
+        // Deterministic white noise, uniform in -amplitude..amplitude
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                amplitude * (2.0 * (state >> 11) as f64 / (1_u64 << 53) as f64 - 1.0)
+            })
+            .collect()
+    }
+
+    fn tone(frequency_hz: f64, length: usize, amplitude: f64) -> Vec<f64> {
+        (0..length)
+            .map(|i| amplitude * f64::sin(2. * PI * frequency_hz * i as f64 / 8000.0))
+            .collect()
+    }
+
+    fn add_noise(channel: &[f64], amplitude: f64) -> Vec<f64> {
+        channel
+            .iter()
+            .zip(noise(channel.len(), amplitude))
+            .map(|(x, n)| x + n)
+            .collect()
+    }
+
+    fn mono_16(samples: Vec<i16>) -> WavFile {
+        // 8 kHz, only the samples matter to the processing methods
+        WavFile::from_subchunks(
+            new_head(0),
+            new_fmt(1, 8000, 16),
+            new_data(0, AudioSamples::MonoI16(samples)),
+        )
+    }
+
+    fn stereo_16(samples: Vec<[i16; 2]>) -> WavFile {
+        WavFile::from_subchunks(
+            new_head(0),
+            new_fmt(2, 8000, 16),
+            new_data(0, AudioSamples::StereoI16(samples)),
+        )
+    }
+
+    fn noisy_tone(length: usize) -> Vec<i16> {
+        // Loud tone on bin 100 of a 2048 point FFT, quieter pseudo-random noise on top
+        (0..length)
+            .map(|t| {
+                let tone = 8000.0 * f64::sin(2. * PI * 100.0 * t as f64 / 2048.0);
+                let noise = 500.0 * (f64::sin(t as f64 * 12.9898) * 43758.5453).fract();
+                (tone + noise).round() as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fades_ramp_the_ends() {
+        let mut wav = mono_16(vec![1000; 2000]);
+        wav.apply_fade(Duration::from_millis(10), Duration::from_millis(20))
+            .unwrap();
+        let after = wav.data.data.to_f64_mono().unwrap();
+
+        // 80 samples in, 160 out
+        assert_eq!(after[0], 0.0);
+        assert_eq!(after[40], 500.0);
+        assert_eq!(after[1919], 500.0);
+        assert_eq!(after[1999], 0.0);
+        assert!(after[80..1840].iter().all(|&x| x == 1000.0));
+
+        // Longer than the file, both ramps cover all of it
+        let mut wav = mono_16(vec![1000; 2000]);
+        wav.apply_fade(Duration::from_secs(1), Duration::from_secs(1))
+            .unwrap();
+        let after = wav.data.data.to_f64_mono().unwrap();
+        assert!((after[1000] - 1000.0 * 0.5 * (999.0 / 2000.0)).abs() <= 1.0);
+    }
+
+    #[test]
+    fn exact_length_denoising_keeps_the_tone() {
+        // Both tones fall exactly on a bin of the 1000 point DFT, so nothing leaks around them
+        let tone = |bin: f64, amplitude: f64, t: usize| {
+            amplitude * f64::sin(2. * PI * bin * t as f64 / 1000.0)
+        };
+        let samples: Vec<i16> = (0..1000)
+            .map(|t| (tone(50.0, 1000.0, t) + tone(123.0, 20.0, t)).round() as i16)
+            .collect();
+
+        let mut wav = mono_16(samples.clone());
+        wav.denoise_data_fft_exact(0.1).unwrap();
+        let after = wav.data.data.to_f64_mono().unwrap();
+        assert_eq!(after.len(), 1000);
+        for (t, sample) in after.iter().enumerate() {
+            assert!(
+                (sample - tone(50.0, 1000.0, t)).abs() <= 2.0,
+                "sample {t}: {sample}"
+            );
+        }
+
+        // Nothing is zeroed without a threshold, the samples come back as they were
+        for length in [FftLength::Truncated, FftLength::Exact] {
+            let mut wav = mono_16(samples.clone());
+            wav.denoise_data(0.0, length).unwrap();
+            let expected = mono_16(samples.clone()).data.data.to_le_bytes_vector();
+            assert_eq!(wav.data.data.to_le_bytes_vector(), expected);
+        }
+    }
+
+    #[test]
+    fn report_counts_the_zeroed_bins() {
+        let mut wav = mono_16(noisy_tone(2000));
+        let report = wav.denoise_data_fft_report(0.2).unwrap();
+        assert_eq!(report.channels.len(), 1);
+
+        let spectrum = &report.channels[0];
+        assert_eq!(spectrum.magnitudes_before.len(), 2048);
+        let max = spectrum
+            .magnitudes_before
+            .iter()
+            .fold(0.0_f64, |a, &b| a.max(b));
+        let below = spectrum
+            .magnitudes_before
+            .iter()
+            .filter(|&&m| m < 0.2 * max)
+            .count();
+        assert!(below > 0 && below < 2048);
+        assert_eq!(spectrum.zeroed_bins, below);
+        let zeros = spectrum
+            .magnitudes_after
+            .iter()
+            .filter(|&&m| m == 0.0)
+            .count();
+        assert_eq!(zeros, below);
+        assert_eq!(report.zeroed_bins(), below);
+
+        // The samples end up the same as without the report
+        let mut expected = mono_16(noisy_tone(2000));
+        expected.denoise_data_fft(0.2, None).unwrap();
+        assert_eq!(
+            wav.data.data.to_le_bytes_vector(),
+            expected.data.data.to_le_bytes_vector()
+        );
+
+        // Every channel gets its own spectrum
+        let samples = noisy_tone(2000);
+        let mut wav = stereo_16(samples.iter().map(|&x| [x, x / 2]).collect());
+        let report = wav.denoise_data_fft_report(0.2).unwrap();
+        assert_eq!(report.channels.len(), 2);
+        assert_eq!(report.zeroed_bins(), below + report.channels[1].zeroed_bins);
+    }
+
+    #[test]
+    fn wet_mix_blends_with_the_original() {
+        let original = mono_16(noisy_tone(3000));
+        let mut denoised = original.clone();
+        denoised.denoise_data_fft(0.3, None).unwrap();
+        let dry = original.channels_f64().unwrap();
+        let wet = denoised.channels_f64().unwrap();
+
+        let mix = |wet: f64| {
+            let mut wav = original.clone();
+            wav.denoise_data_fft_mix(0.3, wet).unwrap();
+            wav.channels_f64().unwrap()
+        };
+        assert_eq!(mix(0.0), dry);
+        assert_eq!(mix(-1.0), dry);
+        assert_eq!(mix(1.0), wet);
+
+        let half = &mix(0.5)[0];
+        for i in 0..3000 {
+            assert!((half[i] - (dry[0][i] + wet[0][i]) / 2.0).abs() <= 0.5);
+        }
+    }
+
+    #[test]
+    fn clones_are_equal_until_modified() {
+        let wav = stereo_16(noisy_tone(100).iter().map(|&x| [x, -x]).collect());
+        let mut copy = wav.clone();
+        assert_eq!(copy, wav);
+
+        copy.fmt.sample_rate = 44100;
+        assert_ne!(copy, wav);
+
+        let mut copy = wav.clone();
+        copy.apply_fade(Duration::from_millis(1), Duration::ZERO)
+            .unwrap();
+        assert_ne!(copy.data, wav.data);
+        assert_eq!((&copy.head, &copy.fmt), (&wav.head, &wav.fmt));
+    }
+
+    #[test]
+    fn silence_is_trimmed_from_both_ends() {
+        // Quiet hiss around the sound, the louder channel decides for both
+        let mut samples = vec![[3, -2]; 800];
+        samples.extend([[0, 1000], [-500, 0], [0, 20], [900, 900]]);
+        samples.extend(vec![[-3, 3]; 1200]);
+        let mut wav = stereo_16(samples);
+        wav.trim_silence(0.01).unwrap();
+        assert_eq!(
+            wav.data.data,
+            AudioSamples::StereoI16(vec![[0, 1000], [-500, 0], [0, 20], [900, 900]])
+        );
+        assert_eq!(wav.data.subchunk_size, 16);
+        assert_eq!(wav.head.chunk_size, 36 + 16);
+
+        let mut silence = mono_16(vec![10; 800]);
+        silence.trim_silence(0.01).unwrap();
+        assert_eq!(silence.data.data, AudioSamples::MonoI16(Vec::new()));
+        assert_eq!(silence.head.chunk_size, 36);
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("wav_file_test_{}_{name}", std::process::id()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn riff_bytes(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        // A file assembled chunk by chunk, for headers the writer would never produce
+        let mut body: Vec<u8> = b"WAVE".to_vec();
+        for (id, content) in chunks {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            body.extend_from_slice(content);
+            if content.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut bytes: Vec<u8> = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    fn fmt_bytes(
+        audio_format: u16,
+        num_channels: u16,
+        sample_rate: u32,
+        byte_rate: u32,
+        block_align: u16,
+        bits_per_sample: u16,
+        extra: &[u8],
+    ) -> Vec<u8> {
+        let mut fmt: Vec<u8> = Vec::new();
+        fmt.extend_from_slice(&audio_format.to_le_bytes());
+        fmt.extend_from_slice(&num_channels.to_le_bytes());
+        fmt.extend_from_slice(&sample_rate.to_le_bytes());
+        fmt.extend_from_slice(&byte_rate.to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits_per_sample.to_le_bytes());
+        fmt.extend_from_slice(extra);
+        fmt
+    }
+
+    fn pcm_bytes(num_frames: usize) -> Vec<u8> {
+        // 16-bit mono ramp, every sample different
+        (0..num_frames as i16)
+            .flat_map(|i| (i * 100).to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn chunk_sizes_past_the_end_are_clamped() {
+        // Streaming encoders leave 0xFFFFFFFF in both sizes, the data ends with half a frame
+        let mut bytes = riff_bytes(&[
+            (b"fmt ", fmt_bytes(1, 1, 8000, 16000, 2, 16, &[])),
+            (b"data", pcm_bytes(10)),
+        ]);
+        bytes[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes.push(0x7F);
+
+        let path = temp_path("clamped.wav");
+        fs::write(&path, &bytes).unwrap();
+        let wav = WavFile::from_wav_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(wav.data.subchunk_size, 20);
+        let expected: Vec<i16> = (0..10).map(|i| i * 100).collect();
+        assert_eq!(wav.data.data, AudioSamples::MonoI16(expected));
+    }
+
+    #[test]
+    fn truncated_headers_are_errors() {
+        let path = temp_path("truncated.wav");
+        let unexpected_length = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            assert!(matches!(
+                WavFile::from_wav_file(&path),
+                Err(WavError::UnexpectedLength)
+            ));
+        };
+        unexpected_length(b"");
+        unexpected_length(b"RIF");
+        unexpected_length(b"RIFF\x04\x00\x00\x00WA");
+        unexpected_length(b"RIFF\x04\x00\x00\x00WAVE");
+        // Cut off in the middle of the fmt chunk header, and of the fmt chunk itself
+        unexpected_length(b"RIFF\x10\x00\x00\x00WAVEfmt \x10\x00");
+        let bytes = riff_bytes(&[(b"fmt ", fmt_bytes(1, 1, 8000, 16000, 2, 16, &[]))]);
+        unexpected_length(&bytes[..30]);
+        // No data chunk at all
+        unexpected_length(&bytes);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn extensible_header_resolves_to_24_bit_pcm() {
+        // WAVE_FORMAT_EXTENSIBLE, stereo 48 kHz with KSDATAFORMAT_SUBTYPE_PCM
+        let mut extension: Vec<u8> = vec![22, 0, 24, 0, 3, 0, 0, 0, 0x01, 0x00];
+        extension.extend_from_slice(&SUB_FORMAT_GUID_SUFFIX);
+        let fmt = fmt_bytes(WAVE_FORMAT_EXTENSIBLE, 2, 48000, 288000, 6, 24, &extension);
+        let data = vec![
+            0x00, 0x00, 0x80, 0xFF, 0xFF, 0x7F, 0x01, 0x00, 0x00, 0xFE, 0xFF, 0xFF,
+        ];
+        let path = temp_path("extensible.wav");
+        fs::write(&path, riff_bytes(&[(b"fmt ", fmt), (b"data", data)])).unwrap();
+        let wav = WavFile::from_wav_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(wav.fmt.audio_format, AudioFormat::Pcm);
+        assert_eq!(wav.fmt.bits_per_sample, 24);
+        assert_eq!(
+            wav.fmt
+                .extension
+                .as_ref()
+                .map(|extension| extension.valid_bits_per_sample),
+            Some(24)
+        );
+        assert_eq!(
+            wav.data.data,
+            AudioSamples::StereoI24(vec![[-8388608, 8388607], [1, -2]])
+        );
+
+        // A GUID of some other format family is left as the extensible tag
+        let mut extension: Vec<u8> = vec![22, 0, 24, 0, 3, 0, 0, 0, 0x01, 0x00];
+        extension.extend_from_slice(&[0; 14]);
+        let fmt = fmt_bytes(WAVE_FORMAT_EXTENSIBLE, 2, 48000, 288000, 6, 24, &extension);
+        fs::write(&path, riff_bytes(&[(b"fmt ", fmt), (b"data", vec![0; 6])])).unwrap();
+        let wav = WavFile::from_wav_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            wav.fmt.audio_format,
+            AudioFormat::Other(WAVE_FORMAT_EXTENSIBLE)
+        );
+    }
+
+    #[test]
+    fn downsampling_filters_out_what_would_alias() {
+        // 6 kHz would fold back to 2 kHz at 8 kHz
+        let samples: Vec<i16> = (0..16000)
+            .map(|i| {
+                let t = i as f64 / 16000.0;
+                let x = 0.4 * f64::sin(2. * PI * 1000.0 * t) + 0.4 * f64::sin(2. * PI * 6000.0 * t);
+                (x * i16::MAX as f64).round() as i16
+            })
+            .collect();
+        let mut wav = mono_16(samples);
+        wav.fmt = new_fmt(1, 16000, 16);
+        wav.downsample_filtered(8000).unwrap();
+        assert_eq!((wav.fmt.sample_rate, wav.fmt.byte_rate), (8000, 16000));
+        assert_eq!(wav.data.subchunk_size, 2 * 8000);
+
+        let (re, im) = fft_real_zero_padded(&wav.data.data.to_f64_mono().unwrap());
+        let n = re.len();
+        let magnitude = |hz: f64| {
+            let k = (hz * n as f64 / 8000.0).round() as usize;
+            (re[k].powi(2) + im[k].powi(2)).sqrt()
+        };
+        assert!(magnitude(2000.0) < 0.01 * magnitude(1000.0));
+
+        for rate in [0, 8000, 16000] {
+            assert!(matches!(
+                wav.clone().downsample_filtered(rate),
+                Err(WavError::InvalidSampleRate(r)) if r == rate
+            ));
+        }
+    }
+
+    #[test]
+    fn append_joins_files_of_the_same_format() {
+        let mut wav = stereo_16(vec![[1, -1], [2, -2]]);
+        wav.append(&stereo_16(vec![[3, -3]])).unwrap();
+        assert_eq!(
+            wav.data.data,
+            AudioSamples::StereoI16(vec![[1, -1], [2, -2], [3, -3]])
+        );
+        assert_eq!(wav.data.subchunk_size, 12);
+        assert_eq!(wav.head.chunk_size, 4 + (8 + 16) + (8 + 12));
+
+        let mismatch = |other: &WavFile| {
+            assert!(matches!(
+                wav.clone().append(other),
+                Err(WavError::FormatMismatch(_))
+            ));
+        };
+        mismatch(&mono_16(vec![1, 2]));
+        let mut other_rate = stereo_16(vec![[1, 1]]);
+        other_rate.fmt = new_fmt(2, 4000, 16);
+        mismatch(&other_rate);
+        let mut other_depth = stereo_16(vec![[1, 1]]);
+        other_depth.fmt = new_fmt(2, 8000, 32);
+        other_depth.data.data = AudioSamples::StereoI32(vec![[1, 1]]);
+        mismatch(&other_depth);
+    }
+
+    #[test]
+    fn mix_sums_and_extends() {
+        let short = mono_16(vec![100, -200, 300]);
+        let long = mono_16(vec![1000, 1000, 1000, 1000, 1001]);
+        let mut mixed = short.clone();
+        mixed.mix(&long, 0.5).unwrap();
+        assert_eq!(
+            mixed.data.data,
+            AudioSamples::MonoI16(vec![600, 300, 800, 500, 501])
+        );
+        assert_eq!(mixed.data.subchunk_size, 10);
+
+        // Out of range sums saturate
+        let mut loud = mono_16(vec![30000, -30000]);
+        loud.mix(&mono_16(vec![30000, -30000]), 1.0).unwrap();
+        assert_eq!(
+            loud.data.data,
+            AudioSamples::MonoI16(vec![i16::MAX, i16::MIN])
+        );
+    }
+
+    #[test]
+    fn sine_peaks_at_its_frequency() {
+        // 1 s at 8 kHz pads to 8192 bins, 1 Hz apart
+        for bits_per_sample in [8, 16, 24, 32] {
+            let wav =
+                WavFile::sine(1000.0, Duration::from_secs(1), 0.8, 8000, bits_per_sample).unwrap();
+            let (re, im) = fft_real_zero_padded(&wav.data.data.to_f64_mono().unwrap());
+            let n = re.len();
+            let peak = (1..n / 2)
+                .max_by(|&a, &b| {
+                    let magnitude = |k: usize| re[k].powi(2) + im[k].powi(2);
+                    magnitude(a).total_cmp(&magnitude(b))
+                })
+                .unwrap();
+            let peak_hz = peak as f64 * 8000.0 / n as f64;
+            assert!((peak_hz - 1000.0).abs() <= 8000.0 / n as f64);
+        }
+    }
+
+    #[test]
+    fn eight_bit_silence_is_stored_as_128() {
+        let silence = WavFile::silence(Duration::from_millis(10), 8000, 2, 8).unwrap();
+        let bytes = silence.create_le_bytes_vector().unwrap();
+        assert_eq!(bytes.len(), 44 + 160);
+        assert!(bytes[44..].iter().all(|&b| b == 0x80));
+    }
+
+    fn adpcm_bytes() -> Vec<u8> {
+        // IMA ADPCM: 4-bit samples in blocks of 256 bytes, 505 samples each
+        let fmt = fmt_bytes(17, 1, 8000, 4055, 256, 4, &[2, 0, 0xF9, 0x01]);
+        let data: Vec<u8> = (0..512).map(|i| (i * 7 % 251) as u8).collect();
+        riff_bytes(&[
+            (b"fmt ", fmt),
+            (b"fact", 1010u32.to_le_bytes().to_vec()),
+            (b"data", data),
+        ])
+    }
+
+    #[test]
+    fn compressed_files_load_but_cannot_be_processed() {
+        let path = temp_path("adpcm.wav");
+        fs::write(&path, adpcm_bytes()).unwrap();
+        let wav = WavFile::from_wav_file(&path).unwrap();
+        assert_eq!(wav.fmt.audio_format, AudioFormat::Other(17));
+        assert_eq!(wav.fmt.block_align, 256);
+        assert_eq!(wav.fmt.byte_rate, 4055);
+        assert_eq!(wav.num_frames(), 1010);
+        let data: Vec<u8> = (0..512).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(wav.data.data, AudioSamples::Encoded(data));
+
+        // Saved back with the very same samples
+        wav.save_to_file(&path).unwrap();
+        assert_eq!(WavFile::from_wav_file(&path).unwrap().data, wav.data);
+        fs::remove_file(&path).unwrap();
+        assert_round_trip_bytes("adpcm", &adpcm_bytes());
+
+        let unsupported = |result: Result<(), WavError>| {
+            assert!(matches!(
+                result,
+                Err(WavError::UnsupportedCompressedFormat(17))
+            ));
+        };
+        unsupported(wav.clone().denoise_data_fft(50.0, None));
+        unsupported(wav.clone().denoise_data_fft_exact(50.0));
+        unsupported(wav.clone().reverse());
+        unsupported(wav.clone().invert_polarity(None));
+        unsupported(wav.clone().set_channels(2));
+        unsupported(wav.clone().apply_fade(Duration::ZERO, Duration::ZERO));
+        unsupported(wav.clone().append(&wav));
+        unsupported(wav.clone().mix(&wav, 1.0));
+        unsupported(wav.clone().downsample_filtered(4000));
+    }
+
+    #[test]
+    fn rms_and_peak_levels() {
+        let wav = WavFile::sine(1000.0, Duration::from_secs(1), 0.5, 8000, 16).unwrap();
+        assert!((wav.rms().unwrap()[0] - 0.5 / 2.0_f64.sqrt()).abs() < 1e-3);
+        assert!((wav.peak().unwrap()[0] - 0.5).abs() < 1e-3);
+
+        let stereo = stereo_16(vec![[16384, 0], [-16384, 0]]);
+        assert_eq!(stereo.rms().unwrap(), vec![0.5, 0.0]);
+        assert_eq!(stereo.peak().unwrap(), vec![0.5, 0.0]);
+
+        let empty = WavFile::silence(Duration::ZERO, 8000, 1, 16).unwrap();
+        assert_eq!(empty.rms().unwrap(), vec![0.0]);
+    }
+
+    #[test]
+    fn slices_copy_a_segment() {
+        let wav = WavFile::sine(440.0, Duration::from_millis(250), 0.5, 8000, 16).unwrap();
+        let slice = wav
+            .slice(Duration::from_millis(50), Duration::from_millis(100))
+            .unwrap();
+        assert_eq!(
+            slice.channels_f64().unwrap()[0],
+            wav.channels_f64().unwrap()[0][400..800]
+        );
+
+        let end = wav
+            .slice(Duration::from_millis(200), Duration::from_secs(9))
+            .unwrap();
+        assert_eq!(end.channels_f64().unwrap()[0].len(), 400);
+
+        for (start, end) in [(100, 100), (100, 50), (300, 400)] {
+            assert!(matches!(
+                wav.slice(Duration::from_millis(start), Duration::from_millis(end)),
+                Err(WavError::InvalidTimeRange)
+            ));
+        }
+    }
+
+    #[test]
+    fn reversing_twice_restores_the_file() {
+        let original = stereo_16(vec![[1, -1], [2, -2], [3, -3]]);
+        let mut wav = original.clone();
+        wav.reverse().unwrap();
+        assert_eq!(
+            wav.data.data,
+            AudioSamples::StereoI16(vec![[3, -3], [2, -2], [1, -1]])
+        );
+        wav.reverse().unwrap();
+        assert_eq!(wav, original);
+    }
+
+    #[test]
+    fn mid_side_keeps_a_centered_signal_centered() {
+        let channel = noisy_tone(3000);
+        let mut denoised = stereo_16(channel.iter().map(|&x| [x, x]).collect());
+        denoised.denoise_data_fft_midside(0.1).unwrap();
+
+        let mut expected = mono_16(channel.clone());
+        expected.denoise_data_fft(0.1, None).unwrap();
+        let AudioSamples::MonoI16(expected_samples) = &expected.data.data else {
+            unreachable!()
+        };
+        let pairs: Vec<[i16; 2]> = expected_samples.iter().map(|&x| [x, x]).collect();
+        assert_eq!(denoised.data.data, AudioSamples::StereoI16(pairs));
+
+        // Mono files are denoised as usual
+        let mut wav = mono_16(channel);
+        wav.denoise_data_fft_midside(0.1).unwrap();
+        assert_eq!(wav, expected);
+    }
+
+    #[test]
+    fn bit_depth_conversions() {
+        let original = stereo_16(noisy_tone(2000).iter().map(|&x| [x, -x]).collect());
+        let mut wav = original.clone();
+        wav.convert_bit_depth(32, false).unwrap();
+        assert_eq!((wav.fmt.block_align, wav.fmt.byte_rate), (8, 64000));
+        assert_eq!(
+            wav.channels_f64().unwrap()[0][..10],
+            original.channels_f64().unwrap()[0][..10]
+                .iter()
+                .map(|x| x * 65536.0)
+                .collect::<Vec<f64>>()
+        );
+        wav.convert_bit_depth(16, false).unwrap();
+        assert_eq!(
+            (&wav.fmt, &wav.data.data),
+            (&original.fmt, &original.data.data)
+        );
+
+        // Down is rounded to the nearest value, the loudest ones saturate
+        let mut wav = WavFile::from_subchunks(
+            new_head(0),
+            new_fmt(1, 8000, 32),
+            new_data(
+                0,
+                AudioSamples::MonoI32(vec![3 * 65536 + 40000, i32::MAX, i32::MIN]),
+            ),
+        );
+        wav.convert_bit_depth(16, false).unwrap();
+        assert_eq!(
+            wav.data.data,
+            AudioSamples::MonoI16(vec![4, i16::MAX, i16::MIN])
+        );
+
+        assert!(matches!(
+            original.clone().convert_bit_depth(12, false),
+            Err(WavError::InvalidWAudioFormat)
+        ));
+    }
+
+    #[test]
+    fn eight_bit_conversion_centers_on_128() {
+        let original = WavFile::sine(1000.0, Duration::from_millis(100), 0.5, 8000, 16).unwrap();
+        let mut wav = original.clone();
+        wav.convert_bit_depth(8, false).unwrap();
+        let path = temp_path("eight_bit_conversion.wav");
+        wav.save_to_file(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        // A whole number of periods, so the unsigned samples average out at the offset
+        let data = &bytes[44..];
+        let mean = data.iter().map(|&b| b as f64).sum::<f64>() / data.len() as f64;
+        assert!((mean - 128.0).abs() < 0.5, "mean {mean}");
+        assert!(data.iter().all(|&b| (63..=193).contains(&b)));
+
+        let reloaded = WavFile::from_wav_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let channel = &reloaded.channels_f64().unwrap()[0];
+        for (a, b) in channel.iter().zip(&original.channels_f64().unwrap()[0]) {
+            assert!((a / 128.0 - b / 32768.0).abs() < 1.0 / 128.0);
+        }
+    }
+
+    #[test]
+    fn denoising_empty_data_fails() {
+        let path = temp_path("empty.wav");
+        let empty_mono = riff_bytes(&[
+            (b"fmt ", fmt_bytes(1, 1, 8000, 16000, 2, 16, &[])),
+            (b"data", Vec::new()),
+        ]);
+        fs::write(&path, empty_mono).unwrap();
+        let empty_mono = WavFile::from_wav_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let empty_stereo = WavFile::silence(Duration::ZERO, 8000, 2, 16).unwrap();
+
+        for wav in [empty_mono, empty_stereo] {
+            let empty = |denoise: fn(&mut WavFile) -> Result<(), WavError>| {
+                let mut denoised = wav.clone();
+                assert!(matches!(
+                    denoise(&mut denoised),
+                    Err(WavError::EmptyAudioData)
+                ));
+                assert_eq!(denoised, wav);
+            };
+            empty(|wav| wav.denoise_data_fft(50.0, None));
+            empty(|wav| wav.denoise_data_fft(50.0, Some(WindowKind::Hann)));
+            empty(|wav| wav.denoise_data_fft_exact(50.0));
+            empty(|wav| wav.denoise_data_fft_padded(50.0));
+            empty(|wav| wav.denoise_data_fft_channels(50.0, &[0]));
+            empty(|wav| wav.denoise_data_fft_sized(50.0, Some(256)));
+            empty(|wav| wav.denoise_data_fft_smooth(50.0, 3));
+            empty(|wav| wav.denoise_data_fft_weighted(50.0, true));
+            empty(|wav| wav.denoise_data_fft_phase_mask(50.0, 1));
+            empty(|wav| wav.denoise_data_fft_mix(50.0, 0.0));
+            empty(|wav| wav.denoise_data_fft_mix(50.0, 0.5));
+            empty(|wav| wav.denoise_data_fft_midside(50.0));
+            empty(|wav| wav.denoise_data_fft_report(50.0).map(|_| ()));
+            empty(|wav| wav.denoise_wiener(&[1.0; 16]));
+            empty(|wav| wav.denoise_moving_average(5));
+            empty(|wav| wav.denoise_median(5));
+            empty(|wav| wav.declick(3.0));
+            empty(|wav| wav.remove_hum(50.0, 3, 4.0));
+            empty(|wav| wav.filtfilt(&BiquadCoeffs::low_pass(1000.0, 8000, 0.707)));
+        }
+    }
+
+    #[test]
+    fn snr_falls_with_more_noise() {
+        let tone = WavFile::sine(440.0, Duration::from_millis(500), 0.5, 8000, 16).unwrap();
+        let with_noise = |amplitude: f64| {
+            let mut noisy = tone.clone();
+            let noise =
+                WavFile::sine(3100.0, Duration::from_millis(500), amplitude, 8000, 16).unwrap();
+            noisy.mix(&noise, 1.0).unwrap();
+            snr_db(&tone, &noisy).unwrap()
+        };
+        let quiet = with_noise(0.005);
+        let loud = with_noise(0.1);
+        assert!((quiet - 40.0).abs() < 0.5, "{quiet} dB");
+        assert!((loud - 14.0).abs() < 0.5, "{loud} dB");
+    }
+
+    #[test]
+    fn snr_edge_cases() {
+        let tone = WavFile::sine(440.0, Duration::from_millis(100), 0.5, 8000, 16).unwrap();
+        let silence = WavFile::silence(Duration::from_millis(100), 8000, 1, 16).unwrap();
+        assert_eq!(snr_db(&tone, &tone).unwrap(), f64::INFINITY);
+        assert_eq!(snr_db(&silence, &silence).unwrap(), f64::INFINITY);
+        assert_eq!(snr_db(&silence, &tone).unwrap(), f64::NEG_INFINITY);
+
+        let empty = WavFile::silence(Duration::ZERO, 8000, 1, 16).unwrap();
+        assert!(matches!(
+            snr_db(&empty, &empty),
+            Err(WavError::EmptyAudioData)
+        ));
+    }
+
+    #[test]
+    fn fmt_extension_bytes_are_written_back() {
+        let path = temp_path("fmt_extension.wav");
+        let saved_bytes = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            let wav = WavFile::from_wav_file(&path).unwrap();
+            wav.save_to_file(&path).unwrap();
+            (wav, fs::read(&path).unwrap())
+        };
+
+        // 18 bytes, just cbSize
+        let bytes = riff_bytes(&[
+            (b"fmt ", fmt_bytes(1, 1, 8000, 16000, 2, 16, &[0, 0])),
+            (b"data", pcm_bytes(8)),
+        ]);
+        let (wav, saved) = saved_bytes(&bytes);
+        assert_eq!(wav.fmt.subchunk_size, 18);
+        assert_eq!(wav.fmt.extra, vec![0, 0]);
+        assert_eq!(saved, bytes);
+
+        // WAVE_FORMAT_EXTENSIBLE keeps its tag and the whole extension
+        let mut extension: Vec<u8> = vec![22, 0, 24, 0, 3, 0, 0, 0, 0x01, 0x00];
+        extension.extend_from_slice(&SUB_FORMAT_GUID_SUFFIX);
+        let fmt = fmt_bytes(WAVE_FORMAT_EXTENSIBLE, 2, 48000, 288000, 6, 24, &extension);
+        let bytes = riff_bytes(&[(b"fmt ", fmt), (b"data", vec![0; 12])]);
+        let (wav, saved) = saved_bytes(&bytes);
+        assert_eq!(wav.fmt.subchunk_size, 40);
+        assert_eq!(saved, bytes);
+
+        // An extension without the bytes of cbSize and the rest cannot be written out
+        let mut short = wav.clone();
+        short.fmt.extra.truncate(2);
+        assert!(matches!(
+            short.save_to_file(&path),
+            Err(WavError::UnexpectedLength)
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn from_samples_round_trips() {
+        let path = temp_path("from_samples.wav");
+        for samples in [
+            AudioSamples::MonoI8(vec![-128, 0, 127]),
+            AudioSamples::StereoI16(vec![[i16::MIN, i16::MAX], [0, -1]]),
+            AudioSamples::MonoI24(vec![-8388608, 1, 8388607]),
+            AudioSamples::StereoI32(vec![[i32::MIN, i32::MAX]]),
+        ] {
+            let wav = WavFile::from_samples(samples, 22050);
+            wav.save_to_file(&path).unwrap();
+            assert_eq!(
+                wav.head.chunk_size as u64 + 8,
+                fs::metadata(&path).unwrap().len()
+            );
+            assert_eq!(WavFile::from_wav_file(&path).unwrap(), wav);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn strict_mode_rejects_inconsistent_fmt() {
+        let path = temp_path("strict.wav");
+        let with_fmt = |byte_rate: u32, block_align: u16| {
+            let bytes = riff_bytes(&[
+                (
+                    b"fmt ",
+                    fmt_bytes(1, 2, 8000, byte_rate, block_align, 16, &[]),
+                ),
+                (b"data", pcm_bytes(8)),
+            ]);
+            fs::write(&path, bytes).unwrap();
+        };
+
+        for (byte_rate, block_align) in [(16000, 4), (32000, 2)] {
+            with_fmt(byte_rate, block_align);
+            assert!(matches!(
+                WavFile::from_wav_file_strict(&path),
+                Err(WavError::InconsistentFmt(_))
+            ));
+            // Otherwise the values are derived from the rest of the header
+            let wav = WavFile::from_wav_file(&path).unwrap();
+            assert_eq!((wav.fmt.byte_rate, wav.fmt.block_align), (32000, 4));
+            assert_eq!(
+                wav.data.data,
+                AudioSamples::StereoI16(vec![[0, 100], [200, 300], [400, 500], [600, 700]])
+            );
+        }
+        with_fmt(32000, 4);
+        assert!(WavFile::from_wav_file_strict(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn padded_denoise_keeps_the_padding() {
+        let mut wav = WavFile::from_samples(AudioSamples::MonoI16(noisy_tone(3000)), 8000);
+        let mut truncated = wav.clone();
+        truncated.denoise_data_fft(0.1, None).unwrap();
+        wav.denoise_data_fft_padded(0.1).unwrap();
+
+        let padded = wav.channels_f64().unwrap();
+        assert_eq!(padded[0].len(), 4096);
+        assert_eq!(wav.data.subchunk_size, 2 * 4096);
+        assert_eq!(padded[0][..3000], truncated.channels_f64().unwrap()[0]);
+    }
+
+    #[test]
+    fn channels_by_index() {
+        let wav = test_tone(2, 16);
+        let (left, right) = wav.data.data.to_f64_stereo().unwrap();
+        assert_eq!(wav.channel(0).unwrap(), left);
+        assert_eq!(wav.channel(1).unwrap(), right);
+        assert!(matches!(wav.channel(2), Err(WavError::InvalidChannel(2))));
+
+        let extracted = wav.extract_channel(1).unwrap();
+        assert_eq!(extracted.fmt, new_fmt(1, 8000, 16));
+        assert_eq!(extracted.channel(0).unwrap(), right);
+        assert!(matches!(
+            wav.extract_channel(5),
+            Err(WavError::InvalidChannel(5))
+        ));
+    }
+
+    #[test]
+    fn gate_silences_the_quiet_parts() {
+        // Loud tone, then nothing but quiet noise
+        let mut samples: Vec<i16> = (0..2000)
+            .map(|t| (16384.0 * f64::sin(2. * PI * 440.0 * t as f64 / 8000.0)).round() as i16)
+            .collect();
+        samples.extend((0..2000).map(|t| ((t * 7919) % 601) as i16 - 300));
+        let original = mono_16(samples);
+        let mut wav = original.clone();
+        wav.noise_gate(0.05, Duration::from_millis(1), Duration::from_millis(10))
+            .unwrap();
+
+        let (before, after) = (original.channel(0).unwrap(), wav.channel(0).unwrap());
+        assert_eq!(after[100..2000], before[100..2000]);
+        assert!(after[2800..].iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn clipped_samples_are_counted() {
+        let wav = WavFile::from_samples(AudioSamples::MonoI16(vec![32767, -32768, 0, 100]), 8000);
+        assert_eq!(wav.clipping_ratio().unwrap(), 0.5);
+        let wav = WavFile::from_samples(AudioSamples::StereoI8(vec![[127, 0]; 4]), 8000);
+        assert_eq!(wav.clipping_ratio().unwrap(), 0.5);
+        let tone = WavFile::sine(440.0, Duration::from_millis(100), 0.5, 8000, 16).unwrap();
+        assert_eq!(tone.clipping_ratio().unwrap(), 0.0);
+        let empty = WavFile::silence(Duration::ZERO, 8000, 2, 16).unwrap();
+        assert_eq!(empty.clipping_ratio().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn pad_byte_after_odd_sized_chunks_is_skipped() {
+        // Odd-sized 8-bit data before fmt, the pad byte after it has to be skipped
+        let data_first = riff_bytes(&[
+            (b"data", vec![0x80, 0xFF, 0x00]),
+            (b"fmt ", fmt_bytes(1, 1, 8000, 8000, 1, 8, &[])),
+        ]);
+        let path = temp_path("odd_data_first.wav");
+        fs::write(&path, data_first).unwrap();
+        let wav = WavFile::from_wav_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(wav.data.data, AudioSamples::MonoI8(vec![0, 127, -128]));
+    }
+
+    #[test]
+    fn alignment_chunks_are_dropped() {
+        let path = temp_path("aligned.wav");
+        let load = |bytes: Vec<u8>| {
+            fs::write(&path, bytes).unwrap();
+            WavFile::from_wav_file(&path).unwrap()
+        };
+        let fmt = fmt_bytes(1, 1, 8000, 16000, 2, 16, &[]);
+        let expected = load(riff_bytes(&[
+            (b"fmt ", fmt.clone()),
+            (b"data", pcm_bytes(8)),
+        ]));
+
+        let aligned = load(riff_bytes(&[
+            (b"JUNK", vec![0; 27]),
+            (b"fmt ", fmt.clone()),
+            (b"PAD ", vec![0; 10]),
+            (b"data", pcm_bytes(8)),
+            (b"JUNK", vec![0; 3]),
+        ]));
+        assert_eq!(aligned, expected);
+
+        // Written back without the alignment chunks, odd-sized data gets its pad byte
+        let odd = load(riff_bytes(&[
+            (b"fmt ", fmt_bytes(1, 1, 8000, 8000, 1, 8, &[])),
+            (b"data", vec![0x80, 0xFF, 0x00]),
+        ]));
+        odd.save_to_file(&path).unwrap();
+        let written = fs::read(&path).unwrap();
+        assert_eq!(written.len(), 48);
+        assert_eq!(odd.head.chunk_size as usize + 8, written.len());
+        assert_eq!(written[47], 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn set_duration_pads_and_truncates() {
+        let original = WavFile::sine(440.0, Duration::from_millis(250), 0.5, 8000, 16).unwrap();
+        let mut wav = original.clone();
+        wav.set_duration(Duration::from_millis(500)).unwrap();
+        assert_eq!(wav.num_frames(), 4000);
+        assert_eq!(wav.data.subchunk_size, 2 * 4000);
+        assert!(wav.channel(0).unwrap()[2000..].iter().all(|&x| x == 0.0));
+        wav.set_duration(Duration::from_millis(100)).unwrap();
+        assert_eq!(wav.channel(0).unwrap(), original.channel(0).unwrap()[..800]);
+    }
+
+    #[test]
+    fn padding_adds_exact_frame_counts() {
+        let original = test_tone(2, 16);
+        let mut wav = original.clone();
+        wav.pad_samples(3, 5).unwrap();
+        assert_eq!(wav.num_frames(), 2008);
+        assert_eq!(wav.head.chunk_size as usize + 8, to_bytes(&wav).len());
+        let channel = wav.channel(1).unwrap();
+        assert!(
+            channel[..3]
+                .iter()
+                .chain(&channel[2003..])
+                .all(|&x| x == 0.0)
+        );
+        assert_eq!(channel[3..2003], original.channel(1).unwrap());
+    }
+
+    #[test]
+    fn pcm_bytes_need_whole_frames() {
+        let wav = WavFile::from_pcm_bytes(&pcm_bytes(4), 2, 8000, 16).unwrap();
+        assert_eq!(
+            wav.data.data,
+            AudioSamples::StereoI16(vec![[0, 100], [200, 300]])
+        );
+        assert_eq!(wav.fmt.byte_rate, 32000);
+
+        assert!(matches!(
+            WavFile::from_pcm_bytes(&pcm_bytes(3), 2, 8000, 16),
+            Err(WavError::UnexpectedLength)
+        ));
+        assert!(matches!(
+            WavFile::from_pcm_bytes(&[0; 4], 0, 8000, 16),
+            Err(WavError::UnexpectedLength)
+        ));
+    }
+
+    #[test]
+    fn dominant_frequency_of_the_downmix() {
+        // 440 and 880 Hz at 0.25 each after the downmix, 1234 Hz at 0.3 in both channels
+        let tone = |frequency: f64, amplitude: f64, t: usize| {
+            amplitude * f64::sin(2. * PI * frequency * t as f64 / 8000.0)
+        };
+        let channels: Vec<Vec<f64>> = [440.0, 880.0]
+            .iter()
+            .map(|&frequency| {
+                (0..2000)
+                    .map(|t| tone(frequency, 0.5, t) + tone(1234.0, 0.3, t))
+                    .collect()
+            })
+            .collect();
+        let wav = WavFile::from_normalized_channels(&channels, 8000, 16).unwrap();
+        assert!((wav.dominant_frequency().unwrap() - 1234.0).abs() <= 8000.0 / 2048.0);
+
+        let empty = WavFile::silence(Duration::ZERO, 8000, 1, 16).unwrap();
+        assert!(matches!(
+            empty.dominant_frequency(),
+            Err(WavError::EmptyAudioData)
+        ));
+    }
+
+    #[test]
+    fn selected_channels_only() {
+        let noisy = noisy_tone(2000);
+        let original = stereo_16(noisy.iter().map(|&x| [x, x / 2]).collect());
+        let mut wav = original.clone();
+        wav.denoise_data_fft_channels(0.2, &[1]).unwrap();
+        assert_eq!(wav.channel(0).unwrap(), original.channel(0).unwrap());
+
+        let mut right = mono_16(noisy.iter().map(|&x| x / 2).collect());
+        right.denoise_data_fft(0.2, None).unwrap();
+        assert_eq!(wav.channel(1).unwrap(), right.channel(0).unwrap());
+        assert_ne!(wav.channel(1).unwrap(), original.channel(1).unwrap());
+
+        assert!(matches!(
+            wav.denoise_data_fft_channels(0.2, &[0, 2]),
+            Err(WavError::InvalidChannel(2))
+        ));
+    }
+
+    #[test]
+    fn metadata_as_json() {
+        let wav = WavFile::from_samples(AudioSamples::StereoI16(vec![[1000, -1000]; 2000]), 8000);
+        let json = wav.metadata_json().unwrap();
+        let metadata: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(metadata["head"]["chunk_id"], "RIFF");
+        assert_eq!(metadata["fmt"]["subchunk_id"], "fmt ");
+        assert_eq!(metadata["fmt"]["audio_format"], "Pcm");
+        assert_eq!(metadata["fmt"]["sample_rate"], 8000);
+        assert_eq!(metadata["data"]["subchunk_size"], 8000);
+        assert_eq!(metadata["num_frames"], 2000);
+        assert_eq!(metadata["duration_secs"], 0.25);
+        assert_eq!(metadata["peak"].as_array().map(Vec::len), Some(2));
+        assert!(metadata["fact"].is_null());
+        assert!(metadata["data"].get("data").is_none());
+    }
+
+    #[test]
+    fn display_summary() {
+        let wav = WavFile::from_samples(AudioSamples::StereoI16(vec![[0, 0]; 2000]), 8000);
+        assert_eq!(
+            wav.to_string(),
+            "WAV 8000Hz 16-bit stereo, 0.25s, 2000 frames"
+        );
+        let wav = WavFile::from_samples(AudioSamples::MonoI8(vec![0; 1010]), 8000);
+        assert_eq!(wav.to_string(), "WAV 8000Hz 8-bit mono, 0.13s, 1010 frames");
+    }
+
+    #[test]
+    fn validate_reads_only_the_headers() {
+        let path = temp_path("validate.wav");
+        let check = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            WavFile::validate(&path)
+        };
+
+        let tone = riff_bytes(&[
+            (b"fmt ", fmt_bytes(1, 2, 8000, 32000, 4, 16, &[])),
+            (b"LIST", vec![0; 5]),
+            (b"data", pcm_bytes(16)),
+        ]);
+        assert!(check(&tone).is_ok());
+        assert!(matches!(
+            check(b"not a wav file"),
+            Err(WavError::NotAWavFile(_))
+        ));
+        let twelve_bit = riff_bytes(&[
+            (b"fmt ", fmt_bytes(1, 1, 8000, 12000, 2, 12, &[])),
+            (b"data", pcm_bytes(8)),
+        ]);
+        assert!(matches!(
+            check(&twelve_bit),
+            Err(WavError::InvalidWAudioFormat)
+        ));
+        let empty = riff_bytes(&[
+            (b"fmt ", fmt_bytes(1, 1, 8000, 16000, 2, 16, &[])),
+            (b"data", Vec::new()),
+        ]);
+        assert!(matches!(check(&empty), Err(WavError::EmptyAudioData)));
+        assert!(matches!(
+            check(&adpcm_bytes()),
+            Err(WavError::UnsupportedCompressedFormat(17))
+        ));
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            WavFile::validate(&path),
+            Err(WavError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn polarity_of_one_or_all_channels() {
+        let original = stereo_16(vec![[1000, -2000], [i16::MIN, 3000]]);
+        let mut wav = original.clone();
+        wav.invert_polarity(Some(0)).unwrap();
+        assert_eq!(
+            wav.data.data,
+            AudioSamples::StereoI16(vec![[-1000, -2000], [i16::MAX, 3000]])
+        );
+        wav.invert_polarity(None).unwrap();
+        assert_eq!(
+            wav.data.data,
+            AudioSamples::StereoI16(vec![[1000, 2000], [-i16::MAX, -3000]])
+        );
+
+        assert!(matches!(
+            wav.invert_polarity(Some(2)),
+            Err(WavError::InvalidChannel(2))
+        ));
+    }
+
+    #[test]
+    fn difference_is_what_denoising_removed() {
+        let original = WavFile::from_samples(AudioSamples::MonoI16(noisy_tone(3000)), 8000);
+        let mut denoised = original.clone();
+        denoised.denoise_data_fft(0.1, None).unwrap();
+
+        let mut restored = original.difference(&denoised).unwrap();
+        assert_ne!(restored, original);
+        restored.mix(&denoised, 1.0).unwrap();
+        assert_eq!(restored, original);
+
+        let shorter = mono_16(noisy_tone(2000));
+        assert!(matches!(
+            original.difference(&shorter),
+            Err(WavError::FormatMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn files_are_read_from_any_reader() {
+        let bytes = riff_bytes(&[
+            (b"fmt ", fmt_bytes(1, 1, 8000, 16000, 2, 16, &[])),
+            (b"data", pcm_bytes(10)),
+        ]);
+        let path = temp_path("reader.wav");
+        fs::write(&path, &bytes).unwrap();
+        let from_file = WavFile::from_wav_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(WavFile::from_reader(bytes.as_slice()).unwrap(), from_file);
+        assert!(WavFile::from_reader(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn written_bytes_match_the_saved_file() {
+        let wav = WavFile::from_samples(
+            AudioSamples::StereoI16(vec![[1, -1], [2, -2], [3, -3]]),
+            8000,
+        );
+        let path = temp_path("write_to.wav");
+        wav.save_to_file(&path).unwrap();
+        let bytes = to_bytes(&wav);
+        assert_eq!(bytes, fs::read(&path).unwrap());
+        assert_eq!(bytes, wav.create_le_bytes_vector().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let reloaded = WavFile::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(reloaded.data.data, wav.data.data);
+    }
+
+    #[test]
+    fn info_tags_round_trip() {
+        let mut wav = WavFile::from_samples(AudioSamples::MonoI16(noisy_tone(101)), 8000);
+        wav.set_info_tag("INAM", "Tone").unwrap();
+        wav.set_info_tag("IART", "Nobody\0").unwrap();
+        wav.set_info_tag("INAM", "Two tones").unwrap();
+        assert!(matches!(
+            wav.set_info_tag("NAME!", "x"),
+            Err(WavError::InvalidInfoTag(_))
+        ));
+
+        let expected = vec![
+            ("INAM".to_string(), "Two tones".to_string()),
+            ("IART".to_string(), "Nobody".to_string()),
+        ];
+        assert_eq!(wav.info_tags(), expected);
+        let bytes = to_bytes(&wav);
+        assert_eq!(wav.head.chunk_size as usize + 8, bytes.len());
+        let reloaded = WavFile::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(reloaded.info_tags(), expected);
+        assert_eq!(reloaded, wav);
+        assert_round_trip_bytes("INFO tags", &bytes);
+
+        // Other lists are skipped
+        let bytes = riff_bytes(&[
+            (b"fmt ", fmt_bytes(1, 1, 8000, 16000, 2, 16, &[])),
+            (b"data", pcm_bytes(8)),
+            (b"LIST", b"adtlnote\x02\x00\x00\x00x\x00".to_vec()),
+        ]);
+        assert!(
+            WavFile::from_reader(bytes.as_slice())
+                .unwrap()
+                .info_tags()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn synthesized_files_round_trip() {
+        for num_channels in [1, 2] {
+            for bits_per_sample in [8, 16, 24, 32] {
+                let bytes = to_bytes(&test_tone(num_channels, bits_per_sample));
+                assert_round_trip_bytes(&format!("{num_channels}ch {bits_per_sample}-bit"), &bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn data_corpus_round_trips() {
+        let checked = assert_round_trip_corpus(concat!(env!("CARGO_MANIFEST_DIR"), "/data"));
+        assert!(checked > 0, "no .wav files in data/");
+        assert_round_trip_corpus(concat!(env!("CARGO_MANIFEST_DIR"), "/data/denoised"));
+    }
+
+    #[test]
+    fn limiter_keeps_peaks_under_the_ceiling() {
+        // A quiet tone with a loud burst in the middle
+        let mut channel: Vec<f64> = (0..8000)
+            .map(|i| 0.3 * f64::sin(2. * PI * 440.0 * i as f64 / 8000.0))
+            .collect();
+        for x in channel[4000..4400].iter_mut() {
+            *x *= 3.0;
+        }
+        let original = WavFile::from_normalized_channels(&[channel], 8000, 16).unwrap();
+        let mut wav = original.clone();
+        wav.limit(0.5).unwrap();
+
+        assert!(wav.peak().unwrap()[0] <= 0.5 + 1.0 / 32768.0);
+        // Nothing changes before the burst, after it the gain recovers over the release time
+        let (before, after) = (original.channel(0).unwrap(), wav.channel(0).unwrap());
+        assert_eq!(after[..3000], before[..3000]);
+        assert!(
+            after[4400..]
+                .iter()
+                .zip(&before[4400..])
+                .all(|(a, b)| a.abs() <= b.abs())
+        );
+        assert!(
+            after[7000..]
+                .iter()
+                .zip(&before[7000..])
+                .all(|(a, b)| (a - b).abs() <= 0.01 * b.abs() + 1.0)
+        );
+    }
+
+    #[test]
+    fn noise_floor_follows_the_pauses() {
+        // Quiet noise all the way through, 0.2 s tone bursts every 0.4 s
+        let noise_amplitude = 0.02;
+        let channel: Vec<f64> = noise(16000, noise_amplitude)
+            .iter()
+            .enumerate()
+            .map(|(i, n)| {
+                let burst = (i / 1600) % 2 == 0;
+                n + if burst {
+                    0.5 * f64::sin(2. * PI * 440.0 * i as f64 / 8000.0)
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let floor = mono(&channel)
+            .adaptive_noise_floor(Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(floor.len(), 16000);
+
+        // Uniform noise has an RMS of amplitude / sqrt(3)
+        let noise_rms = noise_amplitude / 3.0_f64.sqrt();
+        for &level in &floor[4000..] {
+            assert!(
+                level > noise_rms / 2.0 && level < noise_rms * 2.0,
+                "{level}"
+            );
+        }
+    }
+
+    #[test]
+    fn float_files_keep_their_fact_chunk() {
+        // 100 stereo frames of 32-bit float
+        let fmt = fmt_bytes(3, 2, 8000, 64000, 8, 32, &[0, 0]);
+        let data: Vec<u8> = (0..200)
+            .flat_map(|i| (f32::sin(i as f32 / 10.0) * 0.5).to_le_bytes())
+            .collect();
+        let bytes = riff_bytes(&[
+            (b"fmt ", fmt.clone()),
+            (b"fact", 100u32.to_le_bytes().to_vec()),
+            (b"data", data.clone()),
+        ]);
+
+        let wav = WavFile::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(wav.fmt.audio_format, AudioFormat::IeeeFloat);
+        assert_eq!(wav.num_frames(), 100);
+        assert_round_trip_bytes("float", &bytes);
+        let reloaded = WavFile::from_reader(to_bytes(&wav).as_slice()).unwrap();
+        assert_eq!(reloaded.fact.map(|fact| fact.sample_length), Some(100));
+
+        // Without one, it is added with the number of frames the data holds
+        let bytes = riff_bytes(&[(b"fmt ", fmt), (b"data", data)]);
+        let wav = WavFile::from_reader(bytes.as_slice()).unwrap();
+        let reloaded = WavFile::from_reader(to_bytes(&wav).as_slice()).unwrap();
+        assert_eq!(reloaded.fact.map(|fact| fact.sample_length), Some(100));
+        assert_eq!(reloaded.data, wav.data);
+    }
+
+    #[test]
+    fn changing_the_number_of_channels() {
+        let mono_tone = test_tone(1, 16);
+        let mut wav = mono_tone.clone();
+        wav.set_channels(2).unwrap();
+        assert_eq!(
+            (wav.fmt.num_channels, wav.fmt.block_align, wav.fmt.byte_rate),
+            (2, 4, 32000)
+        );
+        assert_eq!(wav.channel(0).unwrap(), mono_tone.channel(0).unwrap());
+        assert_eq!(wav.channel(1).unwrap(), mono_tone.channel(0).unwrap());
+        assert_eq!(wav.head.chunk_size as usize + 8, to_bytes(&wav).len());
+
+        let stereo =
+            WavFile::from_samples(AudioSamples::StereoI16(vec![[100, 300], [-7, 7]]), 8000);
+        let mut wav = stereo.clone();
+        wav.set_channels(1).unwrap();
+        assert_eq!(wav.data.data, AudioSamples::MonoI16(vec![200, 0]));
+
+        assert!(matches!(
+            wav.set_channels(3),
+            Err(WavError::UnsupportedChannelCount(3))
+        ));
+    }
+
+    #[test]
+    fn dc_offset_is_removed() {
+        let channel: Vec<f64> = tone(1000.0, 2000, 0.3).iter().map(|x| x + 0.2).collect();
+        let mut wav = mono(&channel);
+        wav.remove_dc_offset().unwrap();
+        let channel = wav.channel(0).unwrap();
+        let mean = channel.iter().sum::<f64>() / channel.len() as f64;
+        assert!(mean.abs() < 1.0, "{mean}");
+        assert!((wav.peak().unwrap()[0] - 0.3).abs() < 1e-3);
+    }
+
+    #[test]
+    fn samples_export_to_csv() {
+        let path = temp_path("samples.csv");
+        let wav = WavFile::from_samples(AudioSamples::StereoI16(vec![[16384, -32768]; 5]), 8000);
+        wav.export_samples_csv(&path, Some(3)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "index,left,right\n0,0.5,-1\n1,0.5,-1\n2,0.5,-1\n"
+        );
+
+        let wav = WavFile::from_samples(AudioSamples::MonoI16(vec![0, 8192]), 8000);
+        wav.export_samples_csv(&path, None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "index,value\n0,0\n1,0.25\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stereo_channels_are_denoised_separately() {
+        let original = test_tone(2, 16);
+        let mut wav = original.clone();
+        wav.denoise_data_fft(0.2, None).unwrap();
+        for c in 0..2 {
+            let mut channel = original.extract_channel(c).unwrap();
+            channel.denoise_data_fft(0.2, None).unwrap();
+            assert_eq!(wav.channel(c).unwrap(), channel.channel(0).unwrap());
+        }
+    }
+
+    #[test]
+    fn fft_size_has_to_be_a_power_of_two() {
+        let original = test_tone(2, 16);
+        for size in [0, 1, 1000] {
+            assert!(matches!(
+                original.clone().denoise_data_fft_sized(0.1, Some(size)),
+                Err(WavError::InvalidFftSize(s)) if s == size
+            ));
+        }
+
+        let mut sized = original.clone();
+        sized.denoise_data_fft_sized(0.1, None).unwrap();
+        let mut whole = original.clone();
+        whole.denoise_data_fft(0.1, None).unwrap();
+        assert_eq!(sized, whole);
+
+        let mut sized = original.clone();
+        sized.denoise_data_fft_sized(0.1, Some(512)).unwrap();
+        let expected: Vec<f64> = stft_denoise(&original.channel(1).unwrap(), 512, 0.1)
+            .iter()
+            .map(|x| x.round())
+            .collect();
+        assert_eq!(sized.channel(1).unwrap(), expected);
+    }
+
+    #[test]
+    fn other_files_are_not_wav_files() {
+        assert!(matches!(
+            WavFile::from_reader(b"ID3\x03\x00\x00\x00\x00".as_slice()),
+            Err(WavError::NotAWavFile(magic)) if magic == b"ID3\x03"
+        ));
+        assert!(matches!(
+            WavFile::from_reader(b"RIFX\x04\x00\x00\x00WAVE".as_slice()),
+            Err(WavError::InvalidRiffHeader(_))
+        ));
+        assert!(matches!(
+            WavFile::from_reader(b"RIFF\x04\x00\x00\x00AVI ".as_slice()),
+            Err(WavError::InvalidWaveFormat(format)) if format == b"AVI "
+        ));
+    }
+
+    #[test]
+    fn normalizing_to_a_target_rms() {
+        let mut wav = WavFile::sine(1000.0, Duration::from_secs(1), 0.1, 8000, 16).unwrap();
+        wav.normalize_to_rms(0.2).unwrap();
+        assert!((wav.rms().unwrap()[0] - 0.2).abs() < 1e-3);
+
+        // Peaks that would go over full scale are limited
+        wav.normalize_to_rms(0.9).unwrap();
+        assert!(wav.peak().unwrap()[0] <= NORMALIZE_CEILING + 1.0 / 32768.0);
+
+        let silence = WavFile::silence(Duration::from_millis(100), 8000, 2, 16).unwrap();
+        let mut wav = silence.clone();
+        wav.normalize_to_rms(0.2).unwrap();
+        assert_eq!(wav, silence);
+    }
+
+    #[test]
+    fn phase_mask_seeds() {
+        let channel = add_noise(&tone(440.0, 3000, 0.3), 0.05);
+        let stereo =
+            WavFile::from_normalized_channels(&[channel.clone(), channel], 8000, 16).unwrap();
+        let masked = |seed: u64| {
+            let mut wav = stereo.clone();
+            wav.denoise_data_fft_phase_mask(0.1, seed).unwrap();
+            wav
+        };
+
+        assert_eq!(masked(7), masked(7));
+        assert_ne!(masked(7), masked(8));
+        // Both channels get different phases
+        let wav = masked(7);
+        assert_ne!(wav.channel(0).unwrap(), wav.channel(1).unwrap());
+    }
+
+    #[test]
+    fn in_place_denoise_keeps_a_backup() {
+        let path = temp_path("in_place.wav");
+        let backup = format!("{path}.bak");
+        let original = to_bytes(&test_tone(2, 16));
+        fs::write(&path, &original).unwrap();
+
+        WavFile::denoise_in_place(&path, 0.1).unwrap();
+        assert_eq!(fs::read(&backup).unwrap(), original);
+        let mut expected = test_tone(2, 16);
+        expected.denoise_data_fft(0.1, None).unwrap();
+        assert_eq!(WavFile::from_wav_file(&path).unwrap(), expected);
+
+        // A file that cannot be denoised is left alone, and so is the old backup
+        fs::write(&path, b"not a wav file").unwrap();
+        assert!(WavFile::denoise_in_place(&path, 0.1).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"not a wav file");
+        assert_eq!(fs::read(&backup).unwrap(), original);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&backup).unwrap();
+    }
 }
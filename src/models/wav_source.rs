@@ -1,26 +1,44 @@
 use rodio::Source;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
 use std::time::Duration;
 use crate::models::wav_file::WavFile;
 use crate::models::audio_samples::AudioSamples;
+use crate::models::denoise::OverlapSaveDenoiser;
 
 pub struct WavSource {
-    samples: std::vec::IntoIter<i16>,
+    samples: Vec<i16>,
+    position: usize,
+    looping: bool,
     sample_rate: u32,
     channels: u16,
-    samples_per_channel: u32,
 }
 
 impl Iterator for WavSource {
     type Item = i16;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.samples.next()
+        // When looping, the samples start over once exhausted (unless there are none at all)
+        if self.position >= self.samples.len() && self.looping {
+            self.position = 0;
+        }
+        let sample = self.samples.get(self.position).copied()?;
+        self.position += 1;
+        Some(sample)
     }
 }
 
 impl Source for WavSource {
     fn current_frame_len(&self) -> Option<usize> {
-        None
+        // Samples left until the end of the file, or until the next loop begins
+        let remaining = self.samples.len() - self.position;
+        if remaining == 0 && self.looping {
+            Some(self.samples.len())
+        } else {
+            Some(remaining)
+        }
     }
 
     fn channels(&self) -> u16 {
@@ -32,21 +50,49 @@ impl Source for WavSource {
     }
 
     fn total_duration(&self) -> Option<Duration> {
-        Some(Duration::from_secs(self.samples_per_channel as u64 / self.sample_rate as u64))
+        if self.looping {
+            return None;
+        }
+        // Counted from the samples themselves, so every source reports its own length and rate
+        // precisely (whole seconds would cut off the end of the progress bar)
+        Some(samples_to_duration(self.samples.len(), self.channels, self.sample_rate))
     }
 }
 
 impl WavSource {
-    pub fn from_wav_file(wav: &WavFile) -> Self {
+    pub fn from_wav_file(wav: WavFile) -> Self {
+        // 16-bit samples are moved over as they are, the rest is converted
+        let samples = match wav.data.data {
+            AudioSamples::MonoI16(v) => v,
+            samples => Self::from_audio_samples(&samples),
+        };
+        Self::new(samples, wav.fmt.sample_rate, wav.fmt.num_channels)
+    }
+
+    pub fn from_wav_file_ref(wav: &WavFile) -> Self {
+        // Only the converted samples are copied, the file itself is left untouched
+        Self::new(
+            Self::from_audio_samples(&wav.data.data),
+            wav.fmt.sample_rate,
+            wav.fmt.num_channels,
+        )
+    }
+
+    fn new(samples: Vec<i16>, sample_rate: u32, channels: u16) -> Self {
         Self {
-            samples: Self::from_audio_samples(wav.data.data.clone()).into_iter(),
-            sample_rate: wav.fmt.sample_rate,
-            channels: wav.fmt.num_channels,
-            samples_per_channel: wav.data.subchunk_size / wav.fmt.block_align as u32,
+            samples,
+            position: 0,
+            looping: false,
+            sample_rate,
+            channels,
         }
     }
 
-    fn from_audio_samples(samples: AudioSamples) -> Vec<i16> {
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    fn from_audio_samples(samples: &AudioSamples) -> Vec<i16> {
         fn clamp_i32_to_i16(v: i32) -> i16 {
             v.max(i16::MIN as i32).min(i16::MAX as i32) as i16
         }
@@ -56,33 +102,383 @@ impl WavSource {
         }
 
         match samples {
-            AudioSamples::MonoI8(v) => {
-                let data = v.into_iter().map(convert_i8_to_i16).collect();
-                data
-            }
+            AudioSamples::MonoI8(v) => v.iter().map(|&b| convert_i8_to_i16(b)).collect(),
             AudioSamples::StereoI8(v) => {
-                let data = v
-                    .into_iter()
-                    .flat_map(|[l, r]| [convert_i8_to_i16(l), convert_i8_to_i16(r)])
-                    .collect();
-                data
+                v.iter()
+                    .flat_map(|&[l, r]| [convert_i8_to_i16(l), convert_i8_to_i16(r)])
+                    .collect()
             }
-            AudioSamples::MonoI16(v) => v,
-            AudioSamples::StereoI16(v) => {
-                let data = v.into_iter().flat_map(|[l, r]| [l, r]).collect();
-                data
+            AudioSamples::MonoI16(v) => v.clone(),
+            AudioSamples::StereoI16(v) => v.iter().flat_map(|&[l, r]| [l, r]).collect(),
+            AudioSamples::MonoI32(v) => v.iter().map(|&b| clamp_i32_to_i16(b)).collect(),
+            AudioSamples::StereoI32(v) => {
+                v.iter()
+                    .flat_map(|&[l, r]| [clamp_i32_to_i16(l), clamp_i32_to_i16(r)])
+                    .collect()
             }
-            AudioSamples::MonoI32(v) => {
-                let data = v.into_iter().map(clamp_i32_to_i16).collect();
-                data
+            // The top 16 of the 24 bits
+            AudioSamples::MonoI24(v) => v.iter().map(|&b| (b >> 8) as i16).collect(),
+            AudioSamples::StereoI24(v) => {
+                v.iter().flat_map(|&[l, r]| [(l >> 8) as i16, (r >> 8) as i16]).collect()
             }
-            AudioSamples::StereoI32(v) => {
-                let data = v
-                    .into_iter()
-                    .flat_map(|[l, r]| [clamp_i32_to_i16(l), clamp_i32_to_i16(r)])
-                    .collect();
-                data
+            // Nothing that could be played without a decoder
+            AudioSamples::Encoded(_) => Vec::new(),
+        }
+    }
+}
+
+// Denoises the wrapped source block by block while it is being played,
+// so playback can start without denoising the whole file first
+// Every channel goes through its own OverlapSaveDenoiser, which gives the same samples as
+// denoising the whole file with WavFile::denoise_data_fft_sized(_, Some(block_size))
+pub struct DenoisingSource {
+    inner: WavSource,
+    denoisers: Vec<OverlapSaveDenoiser>,
+    block_size: usize,
+    buffer: VecDeque<i16>,
+    finished: bool,
+}
+
+impl DenoisingSource {
+    pub fn new(inner: WavSource, block_size: usize, treshold_percentage: f64) -> Self {
+        let block_size = block_size.max(1);
+        let denoisers = (0..inner.channels())
+            .map(|_| OverlapSaveDenoiser::new(block_size, treshold_percentage))
+            .collect();
+        Self {
+            inner,
+            denoisers,
+            block_size,
+            buffer: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    fn denoise_next_block(&mut self) {
+        // Once the wrapped source runs out, the denoisers hand out the samples they held back
+        let channels = self.denoisers.len();
+        let interleaved: Vec<i16> = self.inner.by_ref().take(self.block_size * channels).collect();
+
+        let denoised: Vec<Vec<f64>> = if interleaved.is_empty() {
+            self.finished = true;
+            self.denoisers.iter_mut().map(|denoiser| denoiser.finish()).collect()
+        } else {
+            self.denoisers
+                .iter_mut()
+                .enumerate()
+                .map(|(c, denoiser)| {
+                    let channel: Vec<f64> = interleaved
+                        .iter()
+                        .skip(c)
+                        .step_by(channels)
+                        .map(|&b| b as f64)
+                        .collect();
+                    denoiser.process_block(&channel)
+                })
+                .collect()
+        };
+
+        for i in 0..denoised.first().map_or(0, Vec::len) {
+            for channel in &denoised {
+                let sample = channel[i].round().clamp(i16::MIN as f64, i16::MAX as f64);
+                self.buffer.push_back(sample as i16);
             }
         }
     }
-}
\ No newline at end of file
+}
+
+impl Iterator for DenoisingSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A block may not complete any output yet, the denoisers lag behind by up to a block
+        while self.buffer.is_empty() && !self.finished {
+            self.denoise_next_block();
+        }
+        self.buffer.pop_front()
+    }
+}
+
+impl Source for DenoisingSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+// Where the playback really is, counted from the samples the audio device has pulled so far
+// Unlike a wall clock it does not move while the sink is paused
+#[derive(Clone)]
+pub struct PlaybackPosition {
+    consumed: Arc<AtomicUsize>,
+    finished: Arc<AtomicBool>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl PlaybackPosition {
+    pub fn position(&self) -> Duration {
+        samples_to_duration(self.consumed.load(Ordering::Relaxed), self.channels, self.sample_rate)
+    }
+
+    // The source played out, or is gone - the sink was stopped or dropped
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+
+    // Blocks until the above, for keeping the output stream alive while the track plays
+    // The sink itself is usually handed over to someone else, so its sleep_until_end is out of reach
+    pub fn wait_until_finished(&self) {
+        while !self.is_finished() {
+            thread::sleep(FINISHED_POLL_INTERVAL);
+        }
+    }
+}
+
+const FINISHED_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+pub fn samples_to_duration(samples: usize, channels: u16, sample_rate: u32) -> Duration {
+    // Interleaved samples, one frame holds a sample of every channel
+    let frames = samples / channels.max(1) as usize;
+    Duration::from_secs_f64(frames as f64 / sample_rate.max(1) as f64)
+}
+
+// Passes the wrapped source through, counting the samples on the way
+pub struct TrackedSource<S> {
+    inner: S,
+    consumed: Arc<AtomicUsize>,
+    finished: Arc<AtomicBool>,
+}
+
+impl<S: Source<Item = i16>> TrackedSource<S> {
+    pub fn new(inner: S) -> (Self, PlaybackPosition) {
+        let consumed = Arc::new(AtomicUsize::new(0));
+        // A source with nothing left to play is done before it is ever pulled
+        let finished = Arc::new(AtomicBool::new(inner.current_frame_len() == Some(0)));
+        let position = PlaybackPosition {
+            consumed: consumed.clone(),
+            finished: finished.clone(),
+            channels: inner.channels(),
+            sample_rate: inner.sample_rate(),
+        };
+        let source = Self {
+            inner,
+            consumed,
+            finished,
+        };
+        (source, position)
+    }
+}
+
+impl<S> Drop for TrackedSource<S> {
+    fn drop(&mut self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for TrackedSource<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(sample) = self.inner.next() else {
+            self.finished.store(true, Ordering::Relaxed);
+            return None;
+        };
+        self.consumed.fetch_add(1, Ordering::Relaxed);
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = i16>> Source for TrackedSource<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::wav_file::{new_data, new_fmt, new_head};
+    use std::f64::consts::PI;
+    use std::sync::mpsc;
+
+    fn wav(samples: AudioSamples, num_channels: u16, bits_per_sample: u16) -> WavFile {
+        WavFile::from_subchunks(
+            new_head(0),
+            new_fmt(num_channels, 8000, bits_per_sample),
+            new_data(0, samples),
+        )
+    }
+
+    fn mono_16(samples: Vec<i16>) -> WavFile {
+        wav(AudioSamples::MonoI16(samples), 1, 16)
+    }
+
+    #[test]
+    fn looping_starts_over() {
+        let mut source = WavSource::from_wav_file(mono_16(vec![1, 2, 3]));
+        assert!(source.total_duration().is_some());
+
+        source.set_looping(true);
+        assert_eq!(source.total_duration(), None);
+        let samples: Vec<i16> = source.by_ref().take(8).collect();
+        assert_eq!(samples, vec![1, 2, 3, 1, 2, 3, 1, 2]);
+        assert_eq!(source.current_frame_len(), Some(1));
+
+        // Nothing to loop over
+        let mut source = WavSource::from_wav_file(mono_16(Vec::new()));
+        source.set_looping(true);
+        assert_eq!(source.next(), None);
+    }
+
+    #[test]
+    fn sources_from_a_file_or_a_reference() {
+        let stereo = wav(AudioSamples::StereoI8(vec![[1, -1], [-128, 127]]), 2, 8);
+        let source = WavSource::from_wav_file_ref(&stereo);
+        assert_eq!(source.current_frame_len(), Some(4));
+        let samples: Vec<i16> = source.collect();
+        assert_eq!(samples, vec![256, -256, -32768, 32512]);
+        assert_eq!(
+            samples,
+            WavSource::from_wav_file(stereo).collect::<Vec<_>>()
+        );
+
+        let mono = mono_16(vec![7, -7, 300]);
+        let samples: Vec<i16> = WavSource::from_wav_file_ref(&mono).collect();
+        assert_eq!(samples, vec![7, -7, 300]);
+        assert_eq!(samples, WavSource::from_wav_file(mono).collect::<Vec<_>>());
+
+        let mono = wav(AudioSamples::MonoI32(vec![i32::MAX, 5, i32::MIN]), 1, 32);
+        let samples: Vec<i16> = WavSource::from_wav_file(mono).collect();
+        assert_eq!(samples, vec![i16::MAX, 5, i16::MIN]);
+
+        let mono = wav(AudioSamples::MonoI24(vec![0x12_3456, -0x80_0000]), 1, 24);
+        let samples: Vec<i16> = WavSource::from_wav_file(mono).collect();
+        assert_eq!(samples, vec![0x1234, i16::MIN]);
+    }
+
+    #[test]
+    fn denoising_source_matches_batch_denoise() {
+        // Stereo 16-bit, a tone under noise that differs between the channels
+        let (left_channel, right_channel): (Vec<f64>, Vec<f64>) = (0..3000)
+            .map(|i| {
+                let tone = 8000.0 * f64::sin(2. * PI * 440.0 * i as f64 / 8000.0);
+                let noise = 1500.0 * f64::sin(i as f64 * 12.9898).fract();
+                (tone + noise, tone - noise)
+            })
+            .unzip();
+        let samples =
+            AudioSamples::from_f64_stereo(&left_channel, &right_channel, 16, false).unwrap();
+        let wav = WavFile::from_samples(samples, 8000);
+
+        let mut batch = wav.clone();
+        batch.denoise_data_fft_sized(40.0, Some(512)).unwrap();
+        let expected: Vec<i16> = WavSource::from_wav_file(batch).collect();
+        let original: Vec<i16> = WavSource::from_wav_file_ref(&wav).collect();
+        assert_ne!(expected, original);
+
+        let streamed: Vec<i16> =
+            DenoisingSource::new(WavSource::from_wav_file(wav), 512, 40.0).collect();
+        assert_eq!(streamed.len(), expected.len());
+        for (i, (s, e)) in streamed.iter().zip(expected.iter()).enumerate() {
+            assert!((s - e).abs() <= 1, "sample {i}: {s} vs {e}");
+        }
+
+        // Nothing held back for an empty source
+        let empty = WavSource::from_wav_file(mono_16(Vec::new()));
+        assert_eq!(DenoisingSource::new(empty, 512, 40.0).next(), None);
+    }
+
+    #[test]
+    fn tracked_source_counts_played_samples() {
+        assert_eq!(samples_to_duration(16000, 2, 8000), Duration::from_secs(1));
+        assert_eq!(samples_to_duration(10, 0, 0), Duration::from_secs(10));
+
+        let wav = WavFile::from_samples(AudioSamples::StereoI16(vec![[0, 0]; 100]), 100);
+        let (mut source, position) = TrackedSource::new(WavSource::from_wav_file(wav));
+        assert_eq!(position.position(), Duration::ZERO);
+
+        source.by_ref().take(100).for_each(drop);
+        assert_eq!(position.position(), Duration::from_millis(500));
+        assert!(!position.is_finished());
+
+        drop(source);
+        assert!(position.is_finished());
+        position.wait_until_finished();
+    }
+
+    fn finishes_soon(position: PlaybackPosition) -> bool {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            position.wait_until_finished();
+            let _ = tx.send(());
+        });
+        rx.recv_timeout(Duration::from_secs(1)).is_ok()
+    }
+
+    #[test]
+    fn waiting_ends_with_the_source() {
+        let source = |samples: Vec<i16>| {
+            WavSource::from_wav_file(WavFile::from_samples(AudioSamples::MonoI16(samples), 8000))
+        };
+
+        // Nothing to play, done before the sink even asks for a sample
+        let (_source, position) = TrackedSource::new(source(Vec::new()));
+        assert!(finishes_soon(position));
+
+        // Played out, while the sink still holds on to the source
+        let (mut played, position) = TrackedSource::new(source(vec![1, 2, 3]));
+        assert!(!position.is_finished());
+        played.by_ref().for_each(drop);
+        assert!(finishes_soon(position));
+
+        // Dropped halfway, like a stopped sink does
+        let (mut stopped, position) = TrackedSource::new(source(vec![1, 2, 3]));
+        stopped.next();
+        assert!(!position.is_finished());
+        drop(stopped);
+        assert!(finishes_soon(position));
+    }
+
+    #[test]
+    fn durations_follow_each_sources_rate() {
+        assert_eq!(samples_to_duration(16000, 2, 8000), Duration::from_secs(1));
+        assert_eq!(
+            samples_to_duration(22050, 1, 44100),
+            Duration::from_millis(500)
+        );
+        assert_eq!(samples_to_duration(10, 0, 0), Duration::from_secs(10));
+
+        let slow = WavFile::from_samples(AudioSamples::MonoI16(vec![0; 4000]), 8000);
+        let fast = WavFile::from_samples(AudioSamples::StereoI16(vec![[0, 0]; 4000]), 16000);
+        let slow = WavSource::from_wav_file(slow);
+        let fast = WavSource::from_wav_file(fast);
+        assert_eq!((slow.sample_rate(), slow.channels()), (8000, 1));
+        assert_eq!((fast.sample_rate(), fast.channels()), (16000, 2));
+        assert_eq!(slow.total_duration(), Some(Duration::from_millis(500)));
+        assert_eq!(fast.total_duration(), Some(Duration::from_millis(250)));
+    }
+}
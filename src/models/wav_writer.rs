@@ -0,0 +1,162 @@
+use std::io::{Seek, SeekFrom, Write};
+use crate::models::audio_samples::AudioSamples;
+use crate::models::errors::WavError;
+use crate::models::wav_file::WavFile;
+
+// Where the sizes sit in the header written by WavWriter::new
+// (RIFF header, a 16 byte PCM fmt chunk and the data chunk header - 44 bytes in total)
+const RIFF_SIZE_OFFSET: u64 = 4;
+const DATA_SIZE_OFFSET: u64 = 40;
+const HEADER_SIZE: u32 = 44;
+
+// Writes a WAV file block by block, so the whole output never has to be held in memory
+// The header goes first with both sizes left at 0, they are patched by finish()
+// once all the samples are known - a writer dropped without finishing leaves them at 0
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    num_channels: u16,
+    bits_per_sample: u16,
+    data_size: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(
+        mut writer: W,
+        num_channels: u16,
+        sample_rate: u32,
+        bits_per_sample: u16,
+    ) -> Result<Self, WavError> {
+        // An empty file of the requested format gives the header, sizes and all
+        let empty = match num_channels {
+            1 => AudioSamples::from_f64_mono(&[], bits_per_sample, false)?,
+            2 => AudioSamples::from_f64_stereo(&[], &[], bits_per_sample, false)?,
+            _ => return Err(WavError::UnsupportedChannelCount(num_channels)),
+        };
+        WavFile::from_samples(empty, sample_rate).write_to(&mut writer)?;
+
+        Ok(Self {
+            writer,
+            num_channels,
+            bits_per_sample,
+            data_size: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &AudioSamples) -> Result<(), WavError> {
+        // Appends the samples after the ones already written, in the format given to new()
+        if samples.num_channels() != self.num_channels
+            || samples.bits_per_sample() != self.bits_per_sample
+        {
+            return Err(WavError::FormatMismatch(format!(
+                "{} channels {}-bit vs {} channels {}-bit",
+                self.num_channels,
+                self.bits_per_sample,
+                samples.num_channels(),
+                samples.bits_per_sample()
+            )));
+        }
+
+        let bytes = samples.to_le_bytes_vector();
+        // The sizes in the header are 32-bit, a longer file could not be read back
+        if HEADER_SIZE as u64 + self.data_size + bytes.len() as u64 > u32::MAX as u64 {
+            return Err(WavError::UnexpectedLength);
+        }
+        self.writer.write_all(&bytes).map_err(WavError::IoError)?;
+        self.data_size += bytes.len() as u64;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, WavError> {
+        // Pads the data chunk to an even size and patches the sizes in the header
+        // The writer is handed back positioned at the end of the file
+        let data_size = self.data_size as u32;
+        let pad = data_size % 2;
+        if pad == 1 {
+            self.writer.write_all(&[0]).map_err(WavError::IoError)?;
+        }
+        let riff_size = HEADER_SIZE - 8 + data_size + pad;
+
+        self.writer
+            .seek(SeekFrom::Start(RIFF_SIZE_OFFSET))
+            .map_err(WavError::IoError)?;
+        self.writer
+            .write_all(&riff_size.to_le_bytes())
+            .map_err(WavError::IoError)?;
+        self.writer
+            .seek(SeekFrom::Start(DATA_SIZE_OFFSET))
+            .map_err(WavError::IoError)?;
+        self.writer
+            .write_all(&data_size.to_le_bytes())
+            .map_err(WavError::IoError)?;
+        self.writer.seek(SeekFrom::End(0)).map_err(WavError::IoError)?;
+        self.writer.flush().map_err(WavError::IoError)?;
+
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn sizes_are_patched_on_finish() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 2, 8000, 16).unwrap();
+        let first = AudioSamples::StereoI16(vec![[1, -1], [2, -2]]);
+        let second = AudioSamples::StereoI16(vec![[3, -3]; 5]);
+        writer.write_samples(&first).unwrap();
+        writer.write_samples(&second).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 28);
+        assert_eq!(&bytes[4..8], &(36 + 28u32).to_le_bytes());
+        assert_eq!(&bytes[40..44], &28u32.to_le_bytes());
+
+        // Same as writing the whole file at once
+        let mut samples = first.clone();
+        samples.append(&second).unwrap();
+        let wav = WavFile::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(wav, WavFile::from_samples(samples, 8000));
+    }
+
+    #[test]
+    fn odd_sized_data_is_padded() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 1, 8000, 8).unwrap();
+        writer
+            .write_samples(&AudioSamples::MonoI8(vec![1, 2, 3]))
+            .unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 4);
+        assert_eq!(&bytes[4..8], &(36 + 4u32).to_le_bytes());
+        assert_eq!(&bytes[40..44], &3u32.to_le_bytes());
+        let wav = WavFile::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(wav.data.data, AudioSamples::MonoI8(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn samples_have_to_match_the_format() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 1, 8000, 16).unwrap();
+        assert!(matches!(
+            writer.write_samples(&AudioSamples::StereoI16(vec![[0, 0]])),
+            Err(WavError::FormatMismatch(_))
+        ));
+        assert!(matches!(
+            writer.write_samples(&AudioSamples::MonoI32(vec![0])),
+            Err(WavError::FormatMismatch(_))
+        ));
+        assert!(matches!(
+            WavWriter::new(Cursor::new(Vec::new()), 3, 8000, 16),
+            Err(WavError::UnsupportedChannelCount(3))
+        ));
+
+        // An empty file is still a valid one
+        let bytes = writer.finish().unwrap().into_inner();
+        assert_eq!(bytes.len(), 44);
+        assert_eq!(
+            WavFile::from_reader(bytes.as_slice()).unwrap().num_frames(),
+            0
+        );
+    }
+}